@@ -1,8 +1,20 @@
+use std::collections::HashMap;
+use std::fs;
+
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
+use ini::ini;
 
+use crate::game_logic::AppState;
+use crate::input::{Action, InputDevices, MenuInput};
+use crate::menu::SettingsReturn;
+use crate::keys;
+use crate::modes;
 
-#[derive(Debug)]
+//配置文件路径
+pub const CONFIG_PATH: &str = "config.ini";
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct KeysConfig {
     pub left: String,
     pub right: String,
@@ -11,7 +23,111 @@ pub struct KeysConfig {
     pub rotate_left: String,
     pub rotate_right: String,
     pub pause: String,
-    pub restart: String
+    pub restart: String,
+    pub quick_restart: String,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self {
+            left: "KeyA".to_string(),
+            right: "KeyD".to_string(),
+            down: "KeyS".to_string(),
+            drop: "Space".to_string(),
+            rotate_left: "KeyJ".to_string(),
+            rotate_right: "KeyK".to_string(),
+            pause: "KeyP".to_string(),
+            restart: "Enter".to_string(),
+            quick_restart: "KeyR".to_string(),
+        }
+    }
+}
+
+impl KeysConfig {
+    //手柄的默认绑定
+    pub fn gamepad_default() -> Self {
+        Self {
+            left: "DPadLeft".to_string(),
+            right: "DPadRight".to_string(),
+            down: "DPadDown".to_string(),
+            drop: "DPadUp".to_string(),
+            rotate_left: "South".to_string(),
+            rotate_right: "East".to_string(),
+            pause: "Start".to_string(),
+            restart: "Select".to_string(),
+            quick_restart: "North".to_string(),
+        }
+    }
+
+    //对战模式中第二个玩家的默认键盘绑定，与第一个玩家不冲突
+    pub fn second_player_default() -> Self {
+        Self {
+            left: "ArrowLeft".to_string(),
+            right: "ArrowRight".to_string(),
+            down: "ArrowDown".to_string(),
+            drop: "ArrowUp".to_string(),
+            rotate_left: "Comma".to_string(),
+            rotate_right: "Period".to_string(),
+            pause: "Backspace".to_string(),
+            restart: "BracketLeft".to_string(),
+            quick_restart: "BracketRight".to_string(),
+        }
+    }
+
+    pub fn get(&self, action: Action) -> &str {
+        match action {
+            Action::Left => &self.left,
+            Action::Right => &self.right,
+            Action::Down => &self.down,
+            Action::Drop => &self.drop,
+            Action::RotateLeft => &self.rotate_left,
+            Action::RotateRight => &self.rotate_right,
+            Action::Pause => &self.pause,
+            Action::Restart => &self.restart,
+            Action::QuickRestart => &self.quick_restart,
+        }
+    }
+
+    pub fn set(&mut self, action: Action, key: &str) {
+        let binding = match action {
+            Action::Left => &mut self.left,
+            Action::Right => &mut self.right,
+            Action::Down => &mut self.down,
+            Action::Drop => &mut self.drop,
+            Action::RotateLeft => &mut self.rotate_left,
+            Action::RotateRight => &mut self.rotate_right,
+            Action::Pause => &mut self.pause,
+            Action::Restart => &mut self.restart,
+            Action::QuickRestart => &mut self.quick_restart,
+        };
+        *binding = key.to_string();
+    }
+
+    //找出绑定到同一个按键的操作
+    pub fn conflicts(&self) -> Vec<(Action, Action)> {
+        let mut result = vec![];
+        for (i, a) in Action::ALL.iter().enumerate() {
+            for b in Action::ALL.iter().skip(i + 1) {
+                if self.get(*a) == self.get(*b) {
+                    result.push((*a, *b));
+                }
+            }
+        }
+        result
+    }
+
+    //找出与另一套绑定使用同一个按键的操作，前一个是本套的操作
+    pub fn conflicts_with(&self, other: &KeysConfig) -> Vec<(Action, Action)> {
+        let mut result = vec![];
+        for a in Action::ALL {
+            for b in Action::ALL {
+                if self.get(a) == other.get(b) {
+                    result.push((a, b));
+                }
+            }
+        }
+        result
+    }
 }
 
 #[derive(Debug)]
@@ -23,29 +139,88 @@ pub struct GameConfig {
     pub tile_size: f32,
     pub border_img: String,
     pub preview_img: String,
+    //窗口失去焦点时自动暂停
+    pub pause_on_focus_loss: bool,
+}
 
+//各游戏模式的参数
+#[derive(Debug)]
+pub struct ModesConfig {
+    //竞速模式的目标行数
+    pub sprint_lines: u32,
+    //限时模式的时长(秒)
+    pub ultra_seconds: u32,
+    //马拉松模式的目标行数，0为无尽
+    pub marathon_lines: u32,
+    //挖掘模式需要消除的垃圾行数
+    pub dig_lines: u32,
+    //棋盘上保持的垃圾行数
+    pub garbage_height: u32,
+    //相邻两行空洞换列的概率，0为同一列，1为每行都换
+    pub garbage_messiness: f64,
+    //垃圾行的随机种子，0表示每局随机
+    pub garbage_seed: u64,
+    //生存模式中垃圾行升起的初始间隔(秒)
+    pub survival_interval: f64,
+    //间隔的下限
+    pub survival_min_interval: f64,
+    //每升起一行后间隔乘以的系数
+    pub survival_acceleration: f64,
+    //计时从第一次操作开始，否则从第一个方块出现时开始
+    pub timer_on_first_input: bool,
+}
+
+impl Default for ModesConfig {
+    fn default() -> Self {
+        Self {
+            sprint_lines: modes::DEFAULT_SPRINT_LINES,
+            ultra_seconds: modes::DEFAULT_ULTRA_SECONDS,
+            marathon_lines: modes::DEFAULT_MARATHON_LINES,
+            dig_lines: modes::DEFAULT_DIG_LINES,
+            garbage_height: 10,
+            garbage_messiness: 0.3,
+            garbage_seed: 0,
+            survival_interval: 8.0,
+            survival_min_interval: 1.0,
+            survival_acceleration: 0.95,
+            timer_on_first_input: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GamepadConfig {
+    pub buttons: KeysConfig,
+    //摇杆死区，超过该值才视为按下方向
+    pub stick_deadzone: f32,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self {
+            buttons: KeysConfig::gamepad_default(),
+            stick_deadzone: 0.5,
+        }
+    }
 }
 
 #[derive(Resource, Debug)]
 pub struct ConfigData {
     pub keys_config: KeysConfig,
-    pub game_config: GameConfig
+    pub gamepad_config: GamepadConfig,
+    //对战模式中第二个玩家的键盘绑定
+    pub versus_keys_config: KeysConfig,
+    pub game_config: GameConfig,
+    pub modes_config: ModesConfig,
 }
 
 impl ConfigData {
 
     pub fn new() -> Self {
         Self {
-            keys_config: KeysConfig {
-                left: "KeyA".to_string(),
-                right: "KeyD".to_string(),
-                down: "KeyS".to_string(),
-                drop: "Space".to_string(),
-                rotate_left: "KeyJ".to_string(),
-                rotate_right: "KeyK".to_string(),
-                pause: "KeyP".to_string(),
-                restart: "Enter".to_string(),
-            },
+            keys_config: KeysConfig::default(),
+            gamepad_config: GamepadConfig::default(),
+            versus_keys_config: KeysConfig::second_player_default(),
             game_config: GameConfig {
                 step_delay: 0.5,
                 first_repeat_delay: 10,  // 约0.15秒@60fps
@@ -54,33 +229,354 @@ impl ConfigData {
                 tile_size: 192.0,
                 border_img: "Border.png".to_string(),
                 preview_img: "Next.png".to_string(),
+                pause_on_focus_loss: true,
+            },
+            modes_config: ModesConfig::default(),
+        }
+    }
+
+    pub fn bindings(&self, set: BindingSet) -> &KeysConfig {
+        match set {
+            BindingSet::Keyboard => &self.keys_config,
+            BindingSet::Gamepad => &self.gamepad_config.buttons,
+            BindingSet::SecondKeyboard => &self.versus_keys_config,
+        }
+    }
+
+    pub fn bindings_mut(&mut self, set: BindingSet) -> &mut KeysConfig {
+        match set {
+            BindingSet::Keyboard => &mut self.keys_config,
+            BindingSet::Gamepad => &mut self.gamepad_config.buttons,
+            BindingSet::SecondKeyboard => &mut self.versus_keys_config,
+        }
+    }
+
+    //写回配置文件
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut content = String::from("[keyboard]\n");
+        for action in Action::ALL {
+            content.push_str(&format!("{} = {}\n", action.name(), self.keys_config.get(action)));
+        }
+        content.push_str("\n[gamepad]\n");
+        for action in Action::ALL {
+            content.push_str(&format!("{} = {}\n", action.name(), self.gamepad_config.buttons.get(action)));
+        }
+        content.push_str(&format!("stick_deadzone = {}\n", self.gamepad_config.stick_deadzone));
+        content.push_str("\n[keyboard2]\n");
+        for action in Action::ALL {
+            content.push_str(&format!("{} = {}\n", action.name(), self.versus_keys_config.get(action)));
+        }
+        content.push_str("\n[game]\n");
+        content.push_str(&format!("step_delay = {}\n", self.game_config.step_delay));
+        content.push_str(&format!("first_repeat_delay = {}\n", self.game_config.first_repeat_delay));
+        content.push_str(&format!("repeat_delay = {}\n", self.game_config.repeat_delay));
+        content.push_str(&format!("scale_factor = {}\n", self.game_config.scale_factor));
+        content.push_str(&format!("tile_size = {}\n", self.game_config.tile_size));
+        content.push_str(&format!("border_img = {}\n", self.game_config.border_img));
+        content.push_str(&format!("preview_img = {}\n", self.game_config.preview_img));
+        content.push_str(&format!("pause_on_focus_loss = {}\n", self.game_config.pause_on_focus_loss));
+        content.push_str("\n[modes]\n");
+        content.push_str(&format!("sprint_lines = {}\n", self.modes_config.sprint_lines));
+        content.push_str(&format!("ultra_seconds = {}\n", self.modes_config.ultra_seconds));
+        content.push_str(&format!("marathon_lines = {}\n", self.modes_config.marathon_lines));
+        content.push_str(&format!("dig_lines = {}\n", self.modes_config.dig_lines));
+        content.push_str(&format!("garbage_height = {}\n", self.modes_config.garbage_height));
+        content.push_str(&format!("garbage_messiness = {}\n", self.modes_config.garbage_messiness));
+        content.push_str(&format!("garbage_seed = {}\n", self.modes_config.garbage_seed));
+        content.push_str(&format!("survival_interval = {}\n", self.modes_config.survival_interval));
+        content.push_str(&format!("survival_min_interval = {}\n", self.modes_config.survival_min_interval));
+        content.push_str(&format!("survival_acceleration = {}\n", self.modes_config.survival_acceleration));
+        content.push_str(&format!("timer_on_first_input = {}\n", self.modes_config.timer_on_first_input));
+        fs::write(path, content)
+    }
+}
+
+type IniMap = HashMap<String, HashMap<String, Option<String>>>;
+
+fn read_value(map: &IniMap, section: &str, key: &str) -> Option<String> {
+    map.get(section)?.get(key)?.clone()
+}
+
+fn read_parsed<T: std::str::FromStr>(map: &IniMap, section: &str, key: &str, value: &mut T) {
+    if let Some(parsed) = read_value(map, section, key).and_then(|v| v.parse().ok()) {
+        *value = parsed;
+    }
+}
+
+//读取配置文件，缺失或无效的项使用默认值
+pub fn load_config(path: &str) -> ConfigData {
+    let mut config = ConfigData::new();
+    let map = match ini!(safe path) {
+        Ok(map) => map,
+        Err(err) => {
+            warn!("failed to load {}: {}, using defaults", path, err);
+            return config;
+        }
+    };
+
+    for action in Action::ALL {
+        if let Some(key) = read_value(&map, "keyboard", action.name()) {
+            if keys::try_from_str(&key).is_some() {
+                config.keys_config.set(action, &key);
+            } else {
+                warn!("invalid key {} for {}, using default", key, action.name());
+            }
+        }
+        if let Some(key) = read_value(&map, "keyboard2", action.name()) {
+            if keys::try_from_str(&key).is_some() {
+                config.versus_keys_config.set(action, &key);
+            } else {
+                warn!("invalid player 2 key {} for {}, using default", key, action.name());
+            }
+        }
+        if let Some(button) = read_value(&map, "gamepad", action.name()) {
+            if keys::gamepad_button_from_str(&button).is_some() {
+                config.gamepad_config.buttons.set(action, &button);
+            } else {
+                warn!("invalid gamepad button {} for {}, using default", button, action.name());
             }
         }
     }
+    read_parsed(&map, "gamepad", "stick_deadzone", &mut config.gamepad_config.stick_deadzone);
+
+    let game_config = &mut config.game_config;
+    read_parsed(&map, "game", "step_delay", &mut game_config.step_delay);
+    read_parsed(&map, "game", "first_repeat_delay", &mut game_config.first_repeat_delay);
+    read_parsed(&map, "game", "repeat_delay", &mut game_config.repeat_delay);
+    read_parsed(&map, "game", "scale_factor", &mut game_config.scale_factor);
+    read_parsed(&map, "game", "tile_size", &mut game_config.tile_size);
+    read_parsed(&map, "game", "border_img", &mut game_config.border_img);
+    read_parsed(&map, "game", "preview_img", &mut game_config.preview_img);
+    read_parsed(&map, "game", "pause_on_focus_loss", &mut game_config.pause_on_focus_loss);
+
+    let modes_config = &mut config.modes_config;
+    read_parsed(&map, "modes", "sprint_lines", &mut modes_config.sprint_lines);
+    read_parsed(&map, "modes", "ultra_seconds", &mut modes_config.ultra_seconds);
+    read_parsed(&map, "modes", "marathon_lines", &mut modes_config.marathon_lines);
+    read_parsed(&map, "modes", "dig_lines", &mut modes_config.dig_lines);
+    read_parsed(&map, "modes", "garbage_height", &mut modes_config.garbage_height);
+    read_parsed(&map, "modes", "garbage_messiness", &mut modes_config.garbage_messiness);
+    read_parsed(&map, "modes", "garbage_seed", &mut modes_config.garbage_seed);
+    read_parsed(&map, "modes", "survival_interval", &mut modes_config.survival_interval);
+    read_parsed(&map, "modes", "survival_min_interval", &mut modes_config.survival_min_interval);
+    read_parsed(&map, "modes", "survival_acceleration", &mut modes_config.survival_acceleration);
+    read_parsed(&map, "modes", "timer_on_first_input", &mut modes_config.timer_on_first_input);
+    config
+}
+
+//可以重新绑定的一组按键
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BindingSet {
+    #[default]
+    Keyboard,
+    Gamepad,
+    //对战模式中第二个玩家的键盘
+    SecondKeyboard,
+}
+
+impl BindingSet {
+    pub const ALL: [BindingSet; 3] = [BindingSet::Keyboard, BindingSet::Gamepad, BindingSet::SecondKeyboard];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BindingSet::Keyboard => "Keyboard",
+            BindingSet::Gamepad => "Gamepad",
+            BindingSet::SecondKeyboard => "Player 2",
+        }
+    }
+}
+
+//正在等待按键输入的操作，set为要绑定的按键组
+#[derive(Resource, Debug, Default)]
+pub struct KeyCapture {
+    pub action: Option<Action>,
+    pub set: BindingSet,
+}
+
+//捕获下一个按下的键，并绑定到正在设置的操作上，Escape取消
+pub fn capture_key(
+    mut capture: ResMut<KeyCapture>,
+    mut config: ResMut<ConfigData>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+) {
+    let Some(action) = capture.action else {
+        return;
+    };
+    if capture.set == BindingSet::Gamepad {
+        if keyboard_input.clear_just_pressed(KeyCode::Escape) {
+            capture.action = None;
+            return;
+        }
+        for gamepad in gamepads.iter() {
+            let button_str = gamepad.digital().get_just_pressed().find_map(|b| keys::gamepad_button_to_str(*b));
+            if let Some(button_str) = button_str {
+                config.gamepad_config.buttons.set(action, button_str);
+                capture.action = None;
+                if let Err(err) = config.save(CONFIG_PATH) {
+                    error!("failed to save {}: {}", CONFIG_PATH, err);
+                }
+                return;
+            }
+        }
+        return;
+    }
+    let pressed: Vec<KeyCode> = keyboard_input.get_just_pressed().copied().collect();
+    for key in pressed {
+        //吞掉这次按键，避免同一帧里触发游戏操作
+        keyboard_input.clear_just_pressed(key);
+        if key == KeyCode::Escape {
+            capture.action = None;
+            return;
+        }
+        if let Some(key_str) = keys::to_str(key) {
+            config.bindings_mut(capture.set).set(action, key_str);
+            capture.action = None;
+            if let Err(err) = config.save(CONFIG_PATH) {
+                error!("failed to save {}: {}", CONFIG_PATH, err);
+            }
+            return;
+        }
+    }
 }
 
 pub fn config_setting_panel(
     mut contexts: EguiContexts,
-    mut state: ResMut<ConfigData>
+    mut state: ResMut<ConfigData>,
+    mut capture: ResMut<KeyCapture>,
+    devices: InputDevices,
+    settings_return: Res<SettingsReturn>,
+    mut next_state: ResMut<NextState<AppState>>,
 ) {
+    //设置按键时Escape用于取消，不返回菜单
+    let mut back = capture.action.is_none() && devices.menu_input() == Some(MenuInput::Back);
     let ctx = contexts.ctx_mut();
     ctx.style_mut(|style| {
         style.spacing.slider_width = 300.0;
     });
+    let mut changed = false;
     egui::SidePanel::left("config_panel")
     .default_width(400.0)
     .show(ctx, |ui| {
         ui.heading("Settings");
 
         ui.add(egui::Label::new("First repeat delay (frames)"));
-        ui.add(egui::Slider::new(&mut state.game_config.first_repeat_delay, 1..=60));
-        
+        changed |= ui.add(egui::Slider::new(&mut state.game_config.first_repeat_delay, 1..=60)).changed();
+
         ui.add(egui::Label::new("Repeat delay (frames)"));
-        ui.add(egui::Slider::new(&mut state.game_config.repeat_delay, 1..=60));
+        changed |= ui.add(egui::Slider::new(&mut state.game_config.repeat_delay, 1..=60)).changed();
+
+        changed |= ui.checkbox(&mut state.game_config.pause_on_focus_loss, "Pause when window loses focus").changed();
+
+        ui.separator();
+        ui.heading("Modes");
+        ui.add(egui::Label::new("Sprint line target"));
+        changed |= ui.add(egui::Slider::new(&mut state.modes_config.sprint_lines, 10..=100).step_by(10.0)).changed();
+        ui.add(egui::Label::new("Ultra time limit (seconds)"));
+        changed |= ui.add(egui::Slider::new(&mut state.modes_config.ultra_seconds, 30..=600).step_by(30.0)).changed();
+        ui.add(egui::Label::new("Marathon line goal"));
+        ui.horizontal(|ui| {
+            for goal in modes::MARATHON_GOALS {
+                let text = if goal == 0 { "Endless".to_string() } else { goal.to_string() };
+                changed |= ui.radio_value(&mut state.modes_config.marathon_lines, goal, text).changed();
+            }
+        });
+        ui.add(egui::Label::new("Dig garbage lines"));
+        changed |= ui.add(egui::Slider::new(&mut state.modes_config.dig_lines, 10..=100)).changed();
+        ui.add(egui::Label::new("Garbage height"));
+        changed |= ui.add(egui::Slider::new(&mut state.modes_config.garbage_height, 1..=16)).changed();
+        ui.add(egui::Label::new("Garbage messiness"));
+        changed |= ui.add(egui::Slider::new(&mut state.modes_config.garbage_messiness, 0.0..=1.0)).changed();
+        ui.add(egui::Label::new("Survival rise interval (seconds)"));
+        changed |= ui.add(egui::Slider::new(&mut state.modes_config.survival_interval, 2.0..=20.0)).changed();
+        changed |= ui.checkbox(&mut state.modes_config.timer_on_first_input, "Start timer on first input").changed();
+
+        ui.separator();
+        ui.heading("Controls");
+        egui::Grid::new("controls_grid").num_columns(7).show(ui, |ui| {
+            ui.label("Action");
+            for set in BindingSet::ALL {
+                ui.label(set.label());
+                ui.label("");
+            }
+            ui.end_row();
+            for action in Action::ALL {
+                ui.label(action.label());
+                for set in BindingSet::ALL {
+                    ui.monospace(state.bindings(set).get(action));
+                    let waiting = capture.action == Some(action) && capture.set == set;
+                    let text = if waiting { "Press a button..." } else { "Rebind" };
+                    if ui.button(text).clicked() {
+                        capture.action = if waiting { None } else { Some(action) };
+                        capture.set = set;
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        if capture.action.is_some() {
+            ui.label("Press Escape to cancel");
+        }
+        for (binding, device) in [(&state.keys_config, "key"), (&state.gamepad_config.buttons, "button"), (&state.versus_keys_config, "player 2 key")] {
+            for (a, b) in binding.conflicts() {
+                ui.colored_label(egui::Color32::RED, format!(
+                    "{} and {} are both bound to {} {}",
+                    a.label(), b.label(), device, binding.get(a)
+                ));
+            }
+        }
+        //两个玩家的键盘同时使用，按键也不能重复
+        for (a, b) in state.keys_config.conflicts_with(&state.versus_keys_config) {
+            ui.colored_label(egui::Color32::RED, format!(
+                "{} and player 2 {} are both bound to key {}",
+                a.label(), b.label(), state.keys_config.get(a)
+            ));
+        }
+
+        ui.add(egui::Label::new("Stick deadzone"));
+        changed |= ui.add(egui::Slider::new(&mut state.gamepad_config.stick_deadzone, 0.1..=0.9)).changed();
+
+        if ui.button("Reset to default").clicked() {
+            state.keys_config = KeysConfig::default();
+            state.gamepad_config = GamepadConfig::default();
+            state.versus_keys_config = KeysConfig::second_player_default();
+            capture.action = None;
+            changed = true;
+        }
 
         ui.separator();
         ui.label("Game running at 120 FPS");
         ui.label("(Fixed update rate)");
 
+        ui.separator();
+        if ui.button("Back").clicked() {
+            back = true;
+        }
         });
-}
\ No newline at end of file
+    if changed {
+        if let Err(err) = state.save(CONFIG_PATH) {
+            error!("failed to save {}: {}", CONFIG_PATH, err);
+        }
+    }
+    if back {
+        capture.action = None;
+        next_state.set(settings_return.0.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_player_keys_do_not_overlap() {
+        assert!(KeysConfig::default().conflicts_with(&KeysConfig::second_player_default()).is_empty());
+    }
+
+    #[test]
+    fn shared_keys_between_players_are_reported() {
+        let mut second = KeysConfig::second_player_default();
+        second.set(Action::Drop, KeysConfig::default().get(Action::Left));
+        assert_eq!(KeysConfig::default().conflicts_with(&second), [(Action::Left, Action::Drop)]);
+    }
+}
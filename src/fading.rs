@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+
+use crate::game_logic::{piece_cells, AppState, TileBoard};
+use crate::modes::GameMode;
+use crate::scene::GameState;
+use crate::stats::GameStats;
+
+//消行时显示整个堆叠的秒数
+const REVEAL_SECONDS: f64 = 1.5;
+//显示结束前逐渐淡出的秒数，方块淡出也使用这个时长
+const FADE_SECONDS: f64 = 0.5;
+
+//方块落定的时间
+#[derive(Component, Debug)]
+pub struct LockedAt(pub f64);
+
+//消行后短暂显示堆叠
+#[derive(Resource, Debug, Default)]
+pub struct StackReveal {
+    //剩余的显示时间
+    pub remaining: f64,
+    //上一次检查时的消行数
+    pub lines: u32,
+}
+
+//方块落定时记录时间，之后按模式淡出
+pub fn mark_locked(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    state: Res<GameState>,
+    tile_board: Res<TileBoard>,
+    time: Res<Time<Virtual>>,
+) {
+    if mode.fade_delay().is_none() {
+        return;
+    }
+    for cell in piece_cells(&state) {
+        if cell.x < 0 || cell.y < 0 || cell.x > 9 || cell.y > 19 {
+            continue;
+        }
+        if let Some(entity) = tile_board.entity_at(cell.x as u32, cell.y as u32) {
+            commands.entity(*entity).insert(LockedAt(time.elapsed_secs_f64()));
+        }
+    }
+}
+
+//消行后开始显示堆叠
+pub fn reveal_on_clear(
+    game_stats: Res<GameStats>,
+    mut reveal: ResMut<StackReveal>,
+) {
+    if game_stats.lines > reveal.lines {
+        reveal.remaining = REVEAL_SECONDS;
+    }
+    reveal.lines = game_stats.lines;
+}
+
+//每局开始时清除上一局的显示状态
+pub fn reset_reveal(
+    mut reveal: ResMut<StackReveal>,
+) {
+    *reveal = StackReveal::default();
+}
+
+//按落定的时间设置方块的透明度，消行时和本局结束后显示整个堆叠，碰撞不受影响
+pub fn update_stack_alpha(
+    mode: Res<GameMode>,
+    app_state: Res<State<AppState>>,
+    time: Res<Time<Virtual>>,
+    mut reveal: ResMut<StackReveal>,
+    mut query: Query<(&mut Sprite, &LockedAt)>,
+) {
+    let Some(delay) = mode.fade_delay() else {
+        return;
+    };
+    reveal.remaining = (reveal.remaining - time.delta_secs_f64()).max(0.0);
+    let game_over = matches!(app_state.get(), AppState::DEAD | AppState::FINISHED);
+    let reveal_alpha = if game_over { 1.0 } else { (reveal.remaining / FADE_SECONDS).min(1.0) };
+    let now = time.elapsed_secs_f64();
+    for (mut sprite, locked_at) in query.iter_mut() {
+        let faded = ((now - locked_at.0 - delay) / FADE_SECONDS).clamp(0.0, 1.0);
+        //不可见模式没有延迟，落定后立即隐藏
+        let alpha = if delay == 0.0 { 0.0 } else { 1.0 - faded };
+        let alpha = alpha.max(reveal_alpha) as f32;
+        if sprite.color.alpha() != alpha {
+            sprite.color.set_alpha(alpha);
+        }
+    }
+}
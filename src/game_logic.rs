@@ -1,90 +1,139 @@
-use bevy::input::ButtonInput;
 use bevy::math::IVec2;
-use bevy::prelude::{AssetServer, Commands, Entity, KeyCode, Query, Res, ResMut, Resource, Time};
-use crate::{config, keys, scene, tetromino};
+use bevy::prelude::{AssetServer, Commands, Entity, Query, Res, ResMut, Resource, Time};
+use crate::{config, garbage, input, modes, scene, stats, tetromino};
 use bevy::{
     prelude::*,
+    window::WindowFocused,
 };
 
+use crate::input::Action;
+use crate::rotation::RotationSystem;
+use crate::rules::{self, Ruleset};
 use crate::scene::{FstPreview, SndPreview};
 
 #[derive(States, Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub enum AppState {
+    //加载资源，完成后进入主菜单
     #[default]
+    LOADING,
+    MAINMENU,
+    MODESELECT,
+    SETTINGS,
+    //重置并开始新的一局
+    STARTING,
     RUNNING,
     PAUSED,
-    DEAD
+    //从暂停恢复前的倒计时
+    COUNTDOWN,
+    DEAD,
+    //达成模式目标，本局结束
+    FINISHED,
 }
 
+//恢复游戏前倒计时的秒数
+const COUNTDOWN_SECONDS: f32 = 3.0;
+//新方块出现的位置(格子坐标)，大方块模式中按格子的大小缩小
+pub fn spawn_position(scale: i32) -> IVec2 {
+    IVec2::new(4 / scale, 18 / scale)
+}
+//不会结束的模式中堆到顶时清除的行数
+const TOP_OUT_CLEAR_ROWS: u32 = 8;
+
+#[derive(Resource, Debug)]
+pub struct Countdown(pub Timer);
+
+#[derive(Component, Debug)]
+pub struct CountdownText;
+
+
+//格子是否被占用，当前方块自身占用的格子不算，顶部以上的格子视为空
+fn is_blocked(
+    state: &scene::GameState,
+    tile_board: &TileBoard,
+    cell: IVec2,
+) -> bool {
+    let own = state.current_tetromino.get_position().iter().any(|p| *p + state.current_position == cell);
+    !own && !has_no_tile(&[cell], state.cell_scale, tile_board)
+}
+
+//一个格子在棋盘上占据的位置，大方块模式中为scale x scale的区域
+pub fn board_cells(cell: IVec2, scale: i32) -> impl Iterator<Item = IVec2> {
+    (0..scale).flat_map(move |dy| (0..scale).map(move |dx| IVec2::new(cell.x * scale + dx, cell.y * scale + dy)))
+}
+
+//当前方块在棋盘上占据的位置
+pub fn piece_cells(state: &scene::GameState) -> Vec<IVec2> {
+    state.current_tetromino.get_position().iter()
+        .flat_map(|p| board_cells(*p + state.current_position, state.cell_scale))
+        .collect()
+}
+
+//当前方块以rotate状态移动offset后是否放得下
+fn fits(
+    state: &scene::GameState,
+    tile_board: &TileBoard,
+    rotate: usize,
+    offset: IVec2,
+) -> bool {
+    state.current_tetromino.get_position2(rotate).iter().all(|p| {
+        !is_blocked(state, tile_board, *p + state.current_position + offset)
+    })
+}
 
 pub fn can_move_left(
     state: &scene::GameState,
     tile_board: &TileBoard
 ) -> bool {
-    let left_most  = state.current_tetromino.left_most_position().iter().map(|p| {
-        IVec2::new(p.x + state.current_position.x - 1 , p.y + state.current_position.y)
-    }).collect::<Vec<IVec2>>();
-    has_no_tile(&left_most, tile_board)
+    fits(state, tile_board, state.current_tetromino.rotate, IVec2::new(-1, 0))
 }
 
 pub fn can_move_right(
     state: &scene::GameState,
     tile_board: &TileBoard
 ) -> bool {
-    let right_most = state.current_tetromino.right_most_position().iter().map(|p| {
-        IVec2::new(p.x + state.current_position.x + 1, p.y + state.current_position.y)
-    }).collect::<Vec<IVec2>>();
-    has_no_tile(&right_most, tile_board)
+    fits(state, tile_board, state.current_tetromino.rotate, IVec2::new(1, 0))
 }
 
+//格子是否都为空，每个格子在棋盘上占scale x scale的区域
 fn has_no_tile(
     position: &[IVec2],
+    scale: i32,
     tile_board: &TileBoard
     // tile_storage: &TileStorage,
 ) -> bool {
-    position.iter().all(|p| {
+    position.iter().flat_map(|cell| board_cells(*cell, scale)).all(|p| {
         let above = p.x >= 0 && p.x <= 9 && p.y > 19;
         let in_and_empty = p.x >= 0 && p.x <= 9 && p.y >= 0 && p.y <= 19 && tile_board.entity_at(p.x as u32,p.y as u32).is_none();
         above || in_and_empty
     })
 }
 
-pub fn can_rotate_left(
-    state: &scene::GameState,
-    tile_board: &TileBoard
-) -> bool {
-    can_rotate(state, tile_board, 3)
-}
-
-fn can_rotate(
-    state: &scene::GameState,
-    tile_board: &TileBoard,
-    i: usize,
-) -> bool {
-    let rotate = (state.current_tetromino.rotate + i) % 4;
-    let org_position = state.current_tetromino.get_position();
-    let rotated_position= state.current_tetromino.get_position2(rotate)
-        .iter().filter(|p| !org_position.contains(p)).map(|p| {
-        IVec2::new(p.x + state.current_position.x, p.y + state.current_position.y)
-    }).collect::<Vec<IVec2>>();
-    has_no_tile(&rotated_position, tile_board)
-}
-
-pub fn can_rotate_right(
-    state: &scene::GameState,
+//按旋转系统旋转当前方块，turn为1时顺时针，为3时逆时针
+fn try_rotate(
+    state: &mut scene::GameState,
     tile_board: &TileBoard,
+    rotation: RotationSystem,
+    turn: usize,
 ) -> bool {
-    can_rotate(state, tile_board, 1)
+    let to = (state.current_tetromino.rotate + turn) % 4;
+    let kick = rotation.rotate(&state.current_tetromino, to, |p| {
+        is_blocked(state, tile_board, p + state.current_position)
+    });
+    match kick {
+        Some(offset) => {
+            state.current_tetromino.rotate = to;
+            state.current_position += offset;
+            true
+        },
+        None => false
+    }
 }
 
 pub fn can_move_down(
     state: &scene::GameState,
     tile_board: &TileBoard,
 ) -> bool {
-    let down_most: Vec<IVec2> = state.current_tetromino.down_most_position().iter().map(|p| {
-        IVec2::new(p.x + state.current_position.x, p.y + state.current_position.y - 1)
-    }).collect();
-    has_no_tile(&down_most, tile_board)
+    fits(state, tile_board, state.current_tetromino.rotate, IVec2::new(0, -1))
 }
 
 
@@ -94,7 +143,9 @@ pub struct TileBoard {
     pub width: u32,
     pub height: u32,
     pub scale_factor: f32,
-    pub tile_size: f32
+    pub tile_size: f32,
+    //棋盘所属的玩家，决定棋盘在屏幕上的位置
+    pub player: usize,
 }
 
 impl TileBoard {
@@ -104,7 +155,8 @@ impl TileBoard {
             width,
             height,
             scale_factor,
-            tile_size
+            tile_size,
+            player: 0,
         }
     }
 
@@ -118,14 +170,10 @@ impl TileBoard {
         result
     }
     
+    //删除并返回一个格子上的方块，格子为空时返回None
     pub fn remove(&mut self, x: u32, y: u32) -> Option<Entity> {
         let idx = y * self.width + x;
-        if let Some(_) = self.tile_map.get(idx as usize) {
-            let result = self.tile_map[idx as usize].unwrap();
-            self.tile_map[idx as usize] = None;
-            return Some(result);
-        }
-        None
+        self.tile_map.get_mut(idx as usize).and_then(|tile| tile.take())
     }
 
     pub fn set(&mut self, x: u32, y: u32, entity: Entity) -> Transform {
@@ -151,7 +199,8 @@ impl TileBoard {
     }
 
     fn get_position(&self, pos: (u32, u32)) -> Transform {
-        let x = (pos.0 as f32 - self.width as f32 / 2.0 + 0.5) * self.tile_size * self.scale_factor;
+        let x = (pos.0 as f32 - self.width as f32 / 2.0 + 0.5) * self.tile_size * self.scale_factor
+            + scene::board_offset(self.player, self.tile_size * self.scale_factor);
         let y = (pos.1 as f32 - self.height as f32 / 2.0 + 0.5) * self.tile_size * self.scale_factor;
         
         Transform::from_scale(Vec3::new(self.scale_factor, self.scale_factor, 1.0)).with_translation(Vec3::new(x, y, 1.0))
@@ -161,75 +210,101 @@ impl TileBoard {
 #[derive(Resource)]
 pub struct Tetrominos([Sprite; 7]);
 #[derive(Resource)]
-pub struct Tiles([Sprite; 7]);
+pub struct Tiles(pub [Sprite; 7]);
 
 //生成方块
 pub fn spawn(
-    mut commands: Commands,
     mut state: ResMut<scene::GameState>,
-    config: Res<config::ConfigData>,
-    _time: Res<Time>,
-    tetrominos: Res<Tetrominos>,
-    mut p1_query: Query<Entity, With<scene::FstPreview>>,
-    mut p2_query: Query<Entity, With<scene::SndPreview>>,
+    mut rng: ResMut<scene::GameRng>,
+    rules: Res<Ruleset>,
     // mut entity_container: ResMut<EntityContainer>,
 ) {
     //重置方块位置，设置成最上面
-    state.current_position = IVec2::new(4, 18);
+    state.current_position = spawn_position(state.cell_scale);
     //使用预览区1的方块创建游戏方块
-    state.current_tetromino = tetromino::Tetromino::new(state.next_tetromino.0, state.next_tetromino.1);
+    state.current_tetromino = rules.rotation.new_piece(state.next_tetromino.0, state.next_tetromino.1);
 
     //预览区2的方块提升到预览区1，预览区2生成新方块
     state.next_tetromino = state.next_tetromino2;
-    state.next_tetromino2 = scene::get_rand_tetromino();
-    
-    //删除预览区的方块精灵
-    if let Ok(p1_entity) = p1_query.get_single_mut() {
-        commands.entity(p1_entity).despawn();
-    }
-    if let Ok(p2_entity) = p2_query.get_single_mut() {
-        commands.entity(p2_entity).despawn();
-    }
-    //重新生成新的预览区方块精灵
-    commands.spawn(tetrominos.0[state.next_tetromino.1].clone())
-        .insert(scene::calculate_preview_transform(&config, true))
-        .insert(FstPreview);
-    commands.spawn(tetrominos.0[state.next_tetromino2.1].clone())
-        .insert(scene::calculate_preview_transform(&config, false))
-        .insert(SndPreview);
+    let previous = state.next_tetromino.1;
+    state.next_tetromino2 = rng.next_piece(rules.randomizer, previous);
     //重置计时器
     state.step_timer = 0.0;
+    state.hit_bottom_timer = 0.0;
+    state.entry_timer = None;
+    state.last_move_rotation = false;
     // 这行代码已经不需要了，因为我们改用帧控制
 }
 
+//重新生成预览区的方块精灵，只替换当前棋盘的预览
+pub fn draw_previews(
+    mut commands: Commands,
+    (state, rng): (Res<scene::GameState>, Res<scene::GameRng>),
+    config: Res<config::ConfigData>,
+    tetrominos: Res<Tetrominos>,
+    rules: Res<Ruleset>,
+    tile_board: Res<TileBoard>,
+    preview_query: PlayerPreviewQuery,
+) {
+    let player = tile_board.player;
+    //删除预览区的方块精灵
+    for (entity, owner) in preview_query.iter() {
+        if owner.0 == player {
+            commands.entity(entity).despawn();
+        }
+    }
+    let shown = rng.shown_previews(rules.previews);
+    if shown > 0 {
+        commands.spawn(tetrominos.0[state.next_tetromino.1].clone())
+            .insert(scene::calculate_preview_transform(&config, player, true))
+            .insert((FstPreview, scene::Player(player)));
+    }
+    if shown > 1 {
+        commands.spawn(tetrominos.0[state.next_tetromino2.1].clone())
+            .insert(scene::calculate_preview_transform(&config, player, false))
+            .insert((SndPreview, scene::Player(player)));
+    }
+}
+
+//按重力下落，一步可以下落多行，20G时直接落到底
 pub fn step_down(
     mut state: ResMut<scene::GameState>,
     config: Res<config::ConfigData>,
     tile_board: Res<TileBoard>,
+    rules: Res<Ruleset>,
+    game_stats: Res<stats::GameStats>,
 ) {
-    if state.step_timer >= config.game_config.step_delay {
-        if !can_move_down(&state, &tile_board) {
-            return;
-        }
+    let timing = rules.timing(game_stats.level, &config.game_config);
+    let rows_per_second = timing.gravity * rules::FRAMES_PER_SECOND;
+    let rows = (state.step_timer * rows_per_second).floor() as u32;
+    let mut moved = 0;
+    while moved < rows && can_move_down(&state, &tile_board) {
         state.current_position = IVec2::new(state.current_position.x, state.current_position.y - 1);
-        state.hit_bottom_timer = 0.0;
+        moved += 1;
+    }
+    if moved == 0 {
+        return;
+    }
+    state.hit_bottom_timer = 0.0;
+    state.last_move_rotation = false;
+    if moved < rows {
+        //已经落到底，多余的下落量作废
         state.step_timer = 0.0;
+    } else {
+        state.step_timer -= moved as f64 / rows_per_second;
     }
 }
 
 fn execute_move_action(
     state: &mut scene::GameState,
-    config: &config::ConfigData,
     tile_board: &TileBoard,
-    action: &str,
+    rotation: RotationSystem,
+    action: Action,
 ) -> bool {
     // 在移动之前，先记录当前方块的位置到tetromino_entities
     // 这样draw_piece就能知道哪些位置的方块需要被移动或删除
-    for position in state.current_tetromino.get_position().iter() {
-        let tile_pos = (
-            (position.x + state.current_position.x) as u32,
-            (position.y + state.current_position.y) as u32
-        );
+    for position in piece_cells(state) {
+        let tile_pos = (position.x as u32, position.y as u32);
         if tile_pos.0 < 10 && tile_pos.1 < 20 {
             if tile_board.entity_at(tile_pos.0, tile_pos.1).is_some() {
                 state.tetromino_entities.insert(tile_pos);
@@ -237,170 +312,127 @@ fn execute_move_action(
         }
     }
     
-    match action {
-        "left" => {
+    let moved = match action {
+        Action::Left => {
             if can_move_left(&state, &tile_board) {
                 state.current_position = IVec2::new(state.current_position.x - 1, state.current_position.y);
                 true
             } else { false }
         },
-        "right" => {
+        Action::Right => {
             if can_move_right(&state, &tile_board) {
                 state.current_position = IVec2::new(state.current_position.x + 1, state.current_position.y);
                 true
             } else { false }
         },
-        "down" => {
+        Action::Down => {
             if can_move_down(&state, &tile_board) {
                 state.current_position = IVec2::new(state.current_position.x, state.current_position.y - 1);
                 state.hit_bottom_timer = 0.0;
                 true
             } else { false }
         },
-        "rotate_left" => {
-            if can_rotate_left(&state, &tile_board) {
-                state.current_tetromino.rotate_left();
-                true
-            } else { false }
-        },
-        "rotate_right" => {
-            if can_rotate_right(&state, &tile_board) {
-                state.current_tetromino.rotate_right();
-                true
-            } else { false }
-        },
-        "drop" => {
+        Action::RotateLeft => try_rotate(state, tile_board, rotation, 3),
+        Action::RotateRight => try_rotate(state, tile_board, rotation, 1),
+        Action::Drop => {
             let mut moved = false;
             while can_move_down(&state, &tile_board) {
                 state.current_position = IVec2::new(state.current_position.x, state.current_position.y - 1);
                 moved = true;
             }
-            if moved {
-                state.hit_bottom_timer += config.game_config.step_delay;
-            }
+            //立即锁定，20G时方块已经在底部，没有移动也要锁定
+            state.hit_bottom_timer = f64::MAX;
             moved
         },
-        _ => false
+        Action::Pause | Action::Restart | Action::QuickRestart => false
+    };
+    if moved {
+        state.last_move_rotation = matches!(action, Action::RotateLeft | Action::RotateRight);
     }
+    moved
 }
 
-pub fn handler_key_down(
+//执行本帧的操作
+pub fn handle_actions(
     mut state: ResMut<scene::GameState>,
-    config: Res<config::ConfigData>,
     tile_board: Res<TileBoard>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    rules: Res<Ruleset>,
+    actions: Res<input::Actions>,
     mut next_state: ResMut<NextState<AppState>>,
-    _time: Res<Time>
 ) {
-    // 处理暂停键
-    if keyboard_input.just_pressed(keys::from_str(config.keys_config.pause.as_str())) {
-        next_state.set(AppState::PAUSED);
-        return;
-    }
-
-    // 检查各种移动键的首次按下
-    let actions = [
-        ("left", config.keys_config.left.as_str()),
-        ("right", config.keys_config.right.as_str()),
-        ("down", config.keys_config.down.as_str()),
-        ("rotate_left", config.keys_config.rotate_left.as_str()),
-        ("rotate_right", config.keys_config.rotate_right.as_str()),
-        ("drop", config.keys_config.drop.as_str()),
-    ];
-
-    for (action, key_str) in actions.iter() {
-        if keyboard_input.just_pressed(keys::from_str(key_str)) {
-            if execute_move_action(&mut *state, &*config, &*tile_board, action) {
-                // 记录按键开始时间和上次重复时间
-                state.key_press_start_frame = Some(state.frame_counter);
-                state.last_repeat_frame = state.frame_counter;
-            }
-            break; // 一次只处理一个按键
+    for action in actions.0.iter() {
+        // 处理暂停键
+        if *action == Action::Pause {
+            next_state.set(AppState::PAUSED);
+            return;
         }
-    }
-}
-
-pub fn handler_key_repeat(
-    mut state: ResMut<scene::GameState>,
-    config: Res<config::ConfigData>,
-    tile_board: Res<TileBoard>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    _next_state: ResMut<NextState<AppState>>,
-    _time: Res<Time>
-) {
-    // 如果没有按键开始记录，直接返回
-    let Some(start_frame) = state.key_press_start_frame else {
-        return;
-    };
-
-    let current_frame = state.frame_counter;
-    let frames_since_press = current_frame - start_frame;
-
-    // 检查是否已经过了首次重复延迟
-    if frames_since_press < config.game_config.first_repeat_delay as u64 {
-        return;
-    }
-
-    // 检查是否到了重复时间
-    let frames_since_last_repeat = current_frame - state.last_repeat_frame;
-    if frames_since_last_repeat < config.game_config.repeat_delay as u64 {
-        return;
-    }
-
-    // 检查哪些键还在按下，并执行相应动作
-    let actions = [
-        ("left", config.keys_config.left.as_str()),
-        ("right", config.keys_config.right.as_str()),
-        ("down", config.keys_config.down.as_str()),
-        ("rotate_left", config.keys_config.rotate_left.as_str()),
-        ("rotate_right", config.keys_config.rotate_right.as_str()),
-        ("drop", config.keys_config.drop.as_str()),
-    ];
-
-    let mut any_key_pressed = false;
-    for (action, key_str) in actions.iter() {
-        if keyboard_input.pressed(keys::from_str(key_str)) {
-            any_key_pressed = true;
-            if execute_move_action(&mut *state, &*config, &*tile_board, action) {
-                state.last_repeat_frame = current_frame;
-            }
-            break; // 一次只处理一个重复动作
+        //出块延迟期间没有可以操作的方块
+        if state.entry_timer.is_none() {
+            execute_move_action(&mut state, &tile_board, rules.rotation, *action);
         }
     }
+}
 
-    // 如果没有按键按下，清除重复状态
-    if !any_key_pressed {
-        state.key_press_start_frame = None;
+//T旋判断：T方块最后一次移动是旋转，且中心四个斜角中至少三个被占(墙和地面算被占)
+fn is_tspin(
+    state: &scene::GameState,
+    tile_board: &TileBoard,
+) -> bool {
+    if state.current_tetromino.tetromino_type != tetromino::TetrominoType::T || !state.last_move_rotation {
+        return false;
     }
+    //中心是与另外三格都相邻的格子
+    let cells = state.current_tetromino.get_position();
+    let Some(center) = cells.iter().find(|c| {
+        cells.iter().filter(|o| (**o - **c).abs().element_sum() == 1).count() == 3
+    }) else {
+        return false;
+    };
+    let center = *center + state.current_position;
+    let corners = [IVec2::new(-1, -1), IVec2::new(1, -1), IVec2::new(-1, 1), IVec2::new(1, 1)];
+    corners.iter().filter(|c| !has_no_tile(&[center + **c], state.cell_scale, tile_board)).count() >= 3
 }
 
 pub fn clear_lines(
     mut commands: Commands,
     state: ResMut<scene::GameState>,
     mut tile_board: ResMut<TileBoard>,
+    mut game_stats: ResMut<stats::GameStats>,
+    garbage_query: Query<(), With<garbage::Garbage>>,
 ) {
+    game_stats.add_piece();
+    let tspin = is_tspin(&state, &tile_board);
 
-    let Some(lowest_y) = state.current_tetromino.down_most_position().iter().map(|p| p.y + state.current_position.y).min() else {
+    let Some(lowest_y) = piece_cells(&state).iter().map(|p| p.y).min() else {
         panic!("should hive lowest.y");
     };
 
 
-    //消除满行
+    //消除满行，大方块模式中方块占的行数加倍
     let mut line_to_remove = lowest_y as u32;
     let mut lines_to_remove = vec![];
-    let mut count = 0;
-    for _i in 0..4 {
+    let mut count: u32 = 0;
+    for _i in 0..4 * state.cell_scale {
         if line_to_remove < 18 && is_full_line(line_to_remove, &tile_board) {
             count += 1;
+            if garbage::is_garbage_line(line_to_remove, &tile_board, &garbage_query) {
+                game_stats.garbage_lines += 1;
+            }
             lines_to_remove.push(line_to_remove);
             clear_line(&mut commands, line_to_remove, tile_board.as_mut());
         }
         line_to_remove += 1;
     }
+    //大方块模式中两行算作一行
+    let count = count.div_ceil(state.cell_scale as u32);
+    if tspin {
+        game_stats.tspins[count.min(3) as usize] += 1;
+    }
     //无可消除行
     if count == 0 {
         return;
     }
+    game_stats.add_lines(count);
 
     let first_line = lines_to_remove[0];
     let mut swap: Vec<(u32, u32)> = vec![];
@@ -423,6 +455,9 @@ pub fn clear_lines(
             }
         }
     }
+    if tile_board.get_all_entitys().is_empty() {
+        game_stats.perfect_clears += 1;
+    }
 }
 
 
@@ -456,6 +491,102 @@ fn is_full_line(
     true
 }
 
+//棋盘上相连(上下左右)的方块分组，每组按从下到上排列，组之间按最低的行排列
+fn connected_groups(tile_board: &TileBoard) -> Vec<Vec<(u32, u32)>> {
+    let (width, height) = (tile_board.width, tile_board.height);
+    let mut visited = vec![false; (width * height) as usize];
+    let mut groups = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            if visited[(y * width + x) as usize] || tile_board.entity_at(x, y).is_none() {
+                continue;
+            }
+            visited[(y * width + x) as usize] = true;
+            let mut group = vec![(x, y)];
+            let mut i = 0;
+            while i < group.len() {
+                let (cx, cy) = group[i];
+                i += 1;
+                let neighbours = [(cx.wrapping_sub(1), cy), (cx + 1, cy), (cx, cy.wrapping_sub(1)), (cx, cy + 1)];
+                for (nx, ny) in neighbours {
+                    if nx >= width || ny >= height || visited[(ny * width + nx) as usize] {
+                        continue;
+                    }
+                    if tile_board.entity_at(nx, ny).is_some() {
+                        visited[(ny * width + nx) as usize] = true;
+                        group.push((nx, ny));
+                    }
+                }
+            }
+            group.sort_by_key(|(_, y)| *y);
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+//一组方块可以整体下落的行数
+fn drop_distance(group: &[(u32, u32)], tile_board: &TileBoard) -> u32 {
+    let mut drop = 0;
+    while group.iter().all(|(x, y)| {
+        *y > drop && (tile_board.entity_at(*x, *y - drop - 1).is_none() || group.contains(&(*x, *y - drop - 1)))
+    }) {
+        drop += 1;
+    }
+    drop
+}
+
+//每组相连的方块各自下落，直到所有的组都落到底
+fn settle_groups(commands: &mut Commands, tile_board: &mut TileBoard) {
+    loop {
+        let mut moved = false;
+        for group in connected_groups(tile_board) {
+            let drop = drop_distance(&group, tile_board);
+            if drop == 0 {
+                continue;
+            }
+            //从下往上移动，目标位置已经空出
+            for (x, y) in group {
+                let (&entity, pos) = tile_board.swap_tile((x, y), (x, y - drop));
+                commands.entity(entity).insert(pos);
+            }
+            moved = true;
+        }
+        if !moved {
+            break;
+        }
+    }
+}
+
+//连锁模式：消行后相连的方块各自下落，形成新的满行时继续消除并计算连锁
+pub fn cascade_lines(
+    mut commands: Commands,
+    mode: Res<modes::GameMode>,
+    mut tile_board: ResMut<TileBoard>,
+    mut game_stats: ResMut<stats::GameStats>,
+) {
+    if !mode.cascades() {
+        return;
+    }
+    //落定时的消除不算连锁，下落后的第一次消除为1连锁
+    let mut chain = 0;
+    loop {
+        settle_groups(&mut commands, &mut tile_board);
+        let full: Vec<u32> = (0..tile_board.height).filter(|y| is_full_line(*y, &tile_board)).collect();
+        if full.is_empty() {
+            break;
+        }
+        chain += 1;
+        for line in full.iter() {
+            clear_line(&mut commands, *line, tile_board.as_mut());
+        }
+        game_stats.add_chain(chain, full.len() as u32);
+    }
+    if chain > 0 && tile_board.get_all_entitys().is_empty() {
+        game_stats.perfect_clears += 1;
+    }
+}
+
 //删除游戏方块，实则仅记录方块原始位置，在后面draw_piece时进行方块移动
 pub fn remove_piece(
     mut state: ResMut<scene::GameState>,
@@ -465,11 +596,8 @@ pub fn remove_piece(
     // 这避免了与手动移动时的位置记录冲突
     if state.tetromino_entities.is_empty() {
         //遍历当前方块的位置，并更新tilemap
-        for positon in state.current_tetromino.get_position().iter() {
-            let tile_pos = (
-                (positon.x + state.current_position.x) as u32,
-                (positon.y + state.current_position.y) as u32
-            );
+        for positon in piece_cells(&state) {
+            let tile_pos = (positon.x as u32, positon.y as u32);
             if tile_pos.0 >= 10 || tile_pos.1 >= 20 {
                 continue;
             }
@@ -488,13 +616,12 @@ pub fn draw_piece(
     mut tile_board: ResMut<TileBoard>,
     mut next_state: ResMut<NextState<AppState>>,
 ) {
-    //获取方块移动的目标位置
-    let positions = state.current_tetromino.get_position().iter().map(|p| {
-        IVec2::new(p.x + state.current_position.x, p.y + state.current_position.y)
-    }).collect::<Vec<IVec2>>();
+    //获取方块移动的目标位置，大方块模式中每格绘制为scale x scale个瓦片
+    let cells = state.current_tetromino.get_position().map(|p| p + state.current_position);
+    let positions = piece_cells(&state);
 
     //spawn出来的方块必须不能有占位
-    if state.tetromino_entities.is_empty() && !has_no_tile(&positions, &tile_board) {
+    if state.tetromino_entities.is_empty() && !has_no_tile(&cells, state.cell_scale, &tile_board) {
         next_state.set(AppState::DEAD);
         return;
     }
@@ -579,14 +706,15 @@ pub fn init_scene(
         scene::make_tile(&asset_server, tetromino::TetrominoType::Z),
     ]);
     commands.insert_resource(tiles);
+    commands.insert_resource(garbage::GarbageTile(scene::make_garbage_tile(&asset_server)));
     //相机
     commands.spawn(Camera2d);
 
     //游戏区域边框
-    commands.spawn(scene::main_board(&asset_server, &config));
+    commands.spawn(scene::main_board(&asset_server, &config, 0));
     //方块预览区1边框
-    commands.spawn(scene::preview_board(&asset_server, &config, true));
-    commands.spawn(scene::preview_board(&asset_server, &config, false));
+    commands.spawn(scene::preview_board(&asset_server, &config, 0, true));
+    commands.spawn(scene::preview_board(&asset_server, &config, 0, false)).insert(scene::SndPreviewFrame);
     //创建fps计数器
     // commands
     //     .spawn((
@@ -616,55 +744,265 @@ pub fn hit_bottom(
     state: Res<scene::GameState>,
     tile_board: Res<TileBoard>,
     config: Res<config::ConfigData>,
+    rules: Res<Ruleset>,
+    game_stats: Res<stats::GameStats>,
 ) -> bool {
-    !can_move_down(&state, &tile_board) && state.hit_bottom_timer >= config.game_config.step_delay
+    let lock_delay = rules.timing(game_stats.level, &config.game_config).lock_delay;
+    state.entry_timer.is_none() && !can_move_down(&state, &tile_board) && state.hit_bottom_timer >= lock_delay
 }
 
+//当前是否有正在下落的方块，出块延迟期间没有
+pub fn piece_active(
+    state: Res<scene::GameState>,
+) -> bool {
+    state.entry_timer.is_none()
+}
 
-pub fn resume (
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+//方块锁定后开始出块延迟
+pub fn start_entry_delay(
+    mut state: ResMut<scene::GameState>,
     config: Res<config::ConfigData>,
+    rules: Res<Ruleset>,
+    game_stats: Res<stats::GameStats>,
+) {
+    state.entry_timer = Some(rules.timing(game_stats.level, &config.game_config).are);
+}
+
+//出块延迟结束，可以生成下一个方块
+pub fn entry_ready(
+    state: Res<scene::GameState>,
+) -> bool {
+    state.entry_timer.is_some_and(|remaining| remaining <= 0.0)
+}
+
+
+//不会结束的模式中，下一个方块放不下时清除最上面的几行，腾出空间
+pub fn clear_top_out(
+    mut commands: Commands,
+    state: Res<scene::GameState>,
+    mode: Res<modes::GameMode>,
+    rules: Res<Ruleset>,
+    mut tile_board: ResMut<TileBoard>,
+) {
+    if mode.tops_out() {
+        return;
+    }
+    let piece = rules.rotation.new_piece(state.next_tetromino.0, state.next_tetromino.1);
+    let position = spawn_position(state.cell_scale);
+    let positions: Vec<IVec2> = piece.get_position().iter().map(|p| *p + position).collect();
+    if has_no_tile(&positions, state.cell_scale, &tile_board) {
+        return;
+    }
+    for y in 20 - TOP_OUT_CLEAR_ROWS..20 {
+        for x in 0..10 {
+            if tile_board.entity_at(x, y).is_some() {
+                let entity = tile_board.remove(x, y).unwrap();
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+pub fn resume (
+    actions: Res<input::Actions>,
     mut next_state: ResMut<NextState<AppState>>
 ) {
-    if keyboard_input.just_pressed(keys::from_str(config.keys_config.pause.as_str())) {
+    if actions.contains(Action::Pause) {
+        next_state.set(AppState::COUNTDOWN);
+    }
+}
+
+//规则只有一个预览时隐藏预览区2的边框
+pub fn update_preview_frames(
+    rules: Res<Ruleset>,
+    mut frame_query: Query<&mut Visibility, With<scene::SndPreviewFrame>>,
+) {
+    for mut visibility in frame_query.iter_mut() {
+        *visibility = if rules.previews > 1 { Visibility::Inherited } else { Visibility::Hidden };
+    }
+}
+
+//两个预览区的方块
+type PreviewQuery<'w, 's> = Query<'w, 's, Entity, Or<(With<FstPreview>, With<SndPreview>)>>;
+//两个预览区的方块和所属的棋盘
+pub type PlayerPreviewQuery<'w, 's> = Query<'w, 's, (Entity, &'static scene::Player), Or<(With<FstPreview>, With<SndPreview>)>>;
+
+//暂停时隐藏棋盘和预览，避免暂停时观察局面
+pub fn hide_board(
+    commands: Commands,
+    tile_board: Res<TileBoard>,
+    preview_query: PreviewQuery,
+) {
+    set_board_visibility(commands, &tile_board, &preview_query, Visibility::Hidden);
+}
+
+pub fn show_board(
+    commands: Commands,
+    tile_board: Res<TileBoard>,
+    preview_query: PreviewQuery,
+) {
+    set_board_visibility(commands, &tile_board, &preview_query, Visibility::Inherited);
+}
+
+fn set_board_visibility(
+    mut commands: Commands,
+    tile_board: &TileBoard,
+    preview_query: &PreviewQuery,
+    visibility: Visibility,
+) {
+    for entity in tile_board.get_all_entitys().into_iter().chain(preview_query.iter()) {
+        commands.entity(entity).insert(visibility);
+    }
+}
+
+//窗口失去焦点时自动暂停
+pub fn pause_on_focus_loss(
+    mut focus_events: EventReader<WindowFocused>,
+    config: Res<config::ConfigData>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let lost_focus = focus_events.read().any(|event| !event.focused);
+    if !lost_focus || !config.game_config.pause_on_focus_loss {
+        return;
+    }
+    if matches!(state.get(), AppState::RUNNING | AppState::COUNTDOWN) {
+        next_state.set(AppState::PAUSED);
+    }
+}
+
+//倒计时数字显示在屏幕中央，对战模式中相机平移并缩小，所以按相机的位置和缩放放置
+pub fn start_countdown(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<Camera2d>>,
+) {
+    let (center, scale) = camera_query.get_single()
+        .map(|(transform, projection)| (transform.translation.truncate(), projection.scale))
+        .unwrap_or((Vec2::ZERO, 1.0));
+    commands.insert_resource(Countdown(Timer::from_seconds(COUNTDOWN_SECONDS, TimerMode::Once)));
+    commands.spawn((
+        Text2d::new(format!("{}", COUNTDOWN_SECONDS as u32)),
+        TextFont {
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+            font_size: 160.0 * scale,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        Transform::from_xyz(center.x, center.y, 10.0),
+        CountdownText,
+    ));
+}
+
+//倒计时结束后恢复游戏，倒计时中再次按暂停键则回到暂停
+pub fn update_countdown(
+    time: Res<Time>,
+    actions: Res<input::Actions>,
+    mut countdown: ResMut<Countdown>,
+    mut text_query: Query<&mut Text2d, With<CountdownText>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if actions.contains(Action::Pause) {
+        next_state.set(AppState::PAUSED);
+        return;
+    }
+    countdown.0.tick(time.delta());
+    if countdown.0.finished() {
         next_state.set(AppState::RUNNING);
+        return;
+    }
+    let remaining = countdown.0.remaining_secs().ceil() as u32;
+    for mut text in text_query.iter_mut() {
+        text.0 = format!("{}", remaining);
     }
 }
 
+pub fn end_countdown(
+    mut commands: Commands,
+    text_query: Query<Entity, With<CountdownText>>,
+) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<Countdown>();
+}
+
 
-pub fn reinit(
+//游戏中、暂停、倒计时和死亡时都可以快速重开
+pub fn quick_restart(
+    actions: Res<input::Actions>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>
+) {
+    let in_game = matches!(state.get(), AppState::RUNNING | AppState::PAUSED | AppState::COUNTDOWN | AppState::DEAD | AppState::FINISHED);
+    if in_game && actions.contains(Action::QuickRestart) {
+        next_state.set(AppState::STARTING);
+    }
+}
+
+//重置本局的所有状态：棋盘、方块队列、计时器和统计
+pub fn reset_game(
     mut commands: Commands,
     mut state: ResMut<scene::GameState>,
-    config: Res<config::ConfigData>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
     mut tile_board: ResMut<TileBoard>,
-    mut next_state: ResMut<NextState<AppState>>
+    mut game_stats: ResMut<stats::GameStats>,
+    mut repeat: ResMut<input::RepeatState>,
+    mut buffer: ResMut<input::InputBuffer>,
+    mut rng: ResMut<scene::GameRng>,
 ) {
-    if keyboard_input.just_pressed(keys::from_str(config.keys_config.restart.as_str())) {
-        //删除所有的方块
-        for entity in tile_board.get_all_entitys() {
-            commands.entity(entity).despawn();
-        }
-        tile_board.clear();
-        //重置触底判定时间
-        state.hit_bottom_timer = 0.0;
-        //重置方块自动下降时间
-        state.step_timer = 0.0;
-        //重置方块位置
-        state.current_position = IVec2::new(4, 19);
-        //重新开始
-        next_state.set(AppState::RUNNING);
+    //删除所有的方块
+    for entity in tile_board.get_all_entitys() {
+        commands.entity(entity).despawn();
     }
+    tile_board.clear();
+    //重新生成方块队列，同时重置位置、计时器和帧计数
+    *rng = scene::GameRng::default();
+    *state = scene::init_game_state(&mut rng.rng);
+    *game_stats = stats::GameStats::default();
+    repeat.key_press_start_frame = None;
+    buffer.discard_presses();
+}
+
+pub fn enter_running(
+    mut next_state: ResMut<NextState<AppState>>
+) {
+    next_state.set(AppState::RUNNING);
+}
+
+//离开游戏状态时停止长按重复，需要重新按键才会继续移动
+pub fn stop_key_repeat(
+    mut repeat: ResMut<input::RepeatState>,
+) {
+    repeat.key_press_start_frame = None;
 }
 
 pub fn update_timer(
     mut state: ResMut<scene::GameState>,
+    mut game_stats: ResMut<stats::GameStats>,
+    config: Res<config::ConfigData>,
+    actions: Res<input::Actions>,
+    buffer: Res<input::InputBuffer>,
     time: Res<Time<Fixed>>
 ) {
     // 使用固定时间步长，确保120fps的一致性
     let fixed_delta = time.delta_secs_f64();
     state.hit_bottom_timer += fixed_delta;
     state.step_timer += fixed_delta;
+    if let Some(remaining) = state.entry_timer.as_mut() {
+        *remaining -= fixed_delta;
+    }
+    let mut timer_step = fixed_delta;
+    if !game_stats.timer_started {
+        let has_input = Action::MOVES.iter().any(|a| actions.contains(*a));
+        game_stats.timer_started = modes::timer_should_start(&config, has_input);
+        //由第一次操作开始计时时，从按下的时间算起，而不是整个步长
+        if game_stats.timer_started && config.modes_config.timer_on_first_input {
+            timer_step = buffer.press_lag(time.elapsed()).min(fixed_delta);
+        }
+    }
+    if game_stats.timer_started {
+        game_stats.elapsed += timer_step;
+    }
     // 更新帧计数器
     state.frame_counter += 1;
 }
@@ -685,4 +1023,239 @@ pub fn update_timer(
 //         print!("\n")
 //     }
 //     print!("\n\n\n")
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::ecs::world::CommandQueue;
+    use bevy::prelude::World;
+    use rand::SeedableRng;
+
+    //10x20的空棋盘，occupied中的格子各放一个方块
+    fn board_with(occupied: &[(u32, u32)]) -> TileBoard {
+        let mut tile_board = TileBoard::new(10, 20, 1.0, 1.0);
+        for (i, (x, y)) in occupied.iter().enumerate() {
+            tile_board.set(*x, *y, Entity::from_raw(i as u32));
+        }
+        tile_board
+    }
+
+    //刚刚旋转到指定方向和位置的T方块
+    fn rotated_t(rotate: usize, position: IVec2) -> scene::GameState {
+        let mut state = scene::init_game_state(&mut rand::rngs::StdRng::seed_from_u64(0));
+        state.current_tetromino = tetromino::Tetromino::new(tetromino::TetrominoType::T, 5);
+        state.current_tetromino.rotate = rotate;
+        state.current_position = position;
+        state.last_move_rotation = true;
+        state
+    }
+
+    #[test]
+    fn tspin_needs_three_occupied_corners() {
+        //朝下的T，中心在(4, 1)
+        let state = rotated_t(2, IVec2::new(4, 0));
+        assert!(is_tspin(&state, &board_with(&[(3, 0), (5, 0), (3, 2)])));
+        assert!(!is_tspin(&state, &board_with(&[(3, 0), (5, 0)])));
+    }
+
+    #[test]
+    fn tspin_needs_a_rotation_and_a_t_piece() {
+        let tile_board = board_with(&[(3, 0), (5, 0), (3, 2)]);
+        let mut state = rotated_t(2, IVec2::new(4, 0));
+        state.last_move_rotation = false;
+        assert!(!is_tspin(&state, &tile_board));
+        let mut state = rotated_t(2, IVec2::new(4, 0));
+        state.current_tetromino = tetromino::Tetromino::new(tetromino::TetrominoType::S, 4);
+        assert!(!is_tspin(&state, &tile_board));
+    }
+
+    #[test]
+    fn floor_corners_count_as_occupied() {
+        //朝上的T贴着地面，中心在(4, 0)，下面的两个斜角是地面
+        let state = rotated_t(0, IVec2::new(4, 0));
+        assert!(is_tspin(&state, &board_with(&[(3, 1)])));
+        assert!(!is_tspin(&state, &board_with(&[])));
+    }
+
+    #[test]
+    fn wall_corners_count_as_occupied() {
+        //朝右的T贴着左墙，中心在(0, 6)，左边的两个斜角是墙
+        let state = rotated_t(1, IVec2::new(0, 5));
+        assert!(is_tspin(&state, &board_with(&[(1, 7)])));
+        assert!(!is_tspin(&state, &board_with(&[])));
+    }
+
+    //在world中生成方块实体，命令才能作用到它们上
+    fn spawn_board(world: &mut World, occupied: &[(u32, u32)]) -> TileBoard {
+        let mut tile_board = TileBoard::new(10, 20, 1.0, 1.0);
+        for (x, y) in occupied {
+            let entity = world.spawn_empty().id();
+            tile_board.set(*x, *y, entity);
+        }
+        tile_board
+    }
+
+    fn occupied(tile_board: &TileBoard) -> Vec<(u32, u32)> {
+        let mut cells = vec![];
+        for y in 0..tile_board.height {
+            for x in 0..tile_board.width {
+                if tile_board.entity_at(x, y).is_some() {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn connected_groups_split_on_gaps() {
+        //底部的L形和悬空的竖条
+        let tile_board = board_with(&[(0, 0), (1, 0), (0, 1), (5, 4), (5, 3)]);
+        let groups = connected_groups(&tile_board);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].len(), 3);
+        assert_eq!(groups[0][0].1, 0);
+        assert_eq!(groups[0][2], (0, 1));
+        assert_eq!(groups[1], [(5, 3), (5, 4)]);
+    }
+
+    #[test]
+    fn drop_distance_stops_on_the_floor_or_other_blocks() {
+        let tile_board = board_with(&[(0, 0), (1, 0), (0, 5), (0, 6), (3, 7)]);
+        //竖条落在(0, 0)上，自己的格子不算阻挡
+        assert_eq!(drop_distance(&[(0, 5), (0, 6)], &tile_board), 4);
+        assert_eq!(drop_distance(&[(3, 7)], &tile_board), 7);
+        assert_eq!(drop_distance(&[(0, 0), (1, 0)], &tile_board), 0);
+    }
+
+    #[test]
+    fn settle_groups_drops_every_loose_group() {
+        let mut world = World::new();
+        //悬空的横条落在底部方块上，另一个悬空方块落到底
+        let mut tile_board = spawn_board(&mut world, &[(0, 0), (0, 3), (1, 3), (6, 9)]);
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        settle_groups(&mut commands, &mut tile_board);
+        queue.apply(&mut world);
+        assert_eq!(occupied(&tile_board), [(0, 0), (6, 0), (0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn cascade_lines_counts_a_two_step_chain() {
+        let mut world = World::new();
+        let mut cells: Vec<(u32, u32)> = (0..9).map(|x| (x, 0)).collect();
+        //第1行缺(4, 1)，(9, 1)下面是第0行的缺口
+        cells.extend((0..10).filter(|x| *x != 4).map(|x| (x, 1)));
+        //(9, 2)压在(9, 1)上，(4, 4)悬空
+        cells.extend([(9, 2), (4, 4)]);
+        let tile_board = spawn_board(&mut world, &cells);
+        world.insert_resource(tile_board);
+        world.insert_resource(modes::GameMode::Cascade);
+        world.insert_resource(stats::GameStats::default());
+        //(4, 4)落下补满第1行，消除后(9, 2)落到第0行的缺口，第0行在第2次连锁中消除
+        world.run_system_once(cascade_lines).unwrap();
+        let game_stats = world.resource::<stats::GameStats>();
+        assert_eq!(game_stats.max_chain, 2);
+        assert_eq!(game_stats.lines, 2);
+        assert_eq!(game_stats.perfect_clears, 1);
+        assert!(world.resource::<TileBoard>().get_all_entitys().is_empty());
+    }
+
+    #[test]
+    fn cascade_lines_only_runs_in_cascade_mode() {
+        let mut world = World::new();
+        let tile_board = spawn_board(&mut world, &[(4, 4)]);
+        world.insert_resource(tile_board);
+        world.insert_resource(modes::GameMode::Endless);
+        world.insert_resource(stats::GameStats::default());
+        world.run_system_once(cascade_lines).unwrap();
+        assert_eq!(occupied(world.resource::<TileBoard>()), [(4, 4)]);
+    }
+
+    #[test]
+    fn hard_drop_locks_at_once_under_20g() {
+        let mut world = World::new();
+        world.insert_resource(scene::init_game_state(&mut rand::rngs::StdRng::seed_from_u64(0)));
+        world.insert_resource(scene::GameRng::new(0));
+        world.insert_resource(TileBoard::new(10, 20, 1.0, 1.0));
+        world.insert_resource(Ruleset::tgm());
+        world.insert_resource(config::ConfigData::new());
+        world.insert_resource(stats::GameStats { level: 0, scoring: rules::Scoring::Master, ..Default::default() });
+        world.insert_resource(input::Actions(vec![Action::Drop]));
+        world.insert_resource(NextState::<AppState>::default());
+        //生成后的第一步就落到底部，还在锁定延迟中
+        world.run_system_once(spawn).unwrap();
+        world.resource_mut::<scene::GameState>().step_timer = 1.0 / rules::FRAMES_PER_SECOND;
+        world.run_system_once(step_down).unwrap();
+        assert!(!can_move_down(world.resource::<scene::GameState>(), world.resource::<TileBoard>()));
+        assert!(!world.run_system_once(hit_bottom).unwrap());
+        world.run_system_once(handle_actions).unwrap();
+        assert!(world.run_system_once(hit_bottom).unwrap());
+    }
+
+    //大方块模式中停在position的O方块
+    fn big_o(position: IVec2) -> scene::GameState {
+        let mut state = scene::init_game_state(&mut rand::rngs::StdRng::seed_from_u64(0));
+        state.current_tetromino = tetromino::Tetromino::new(tetromino::TetrominoType::O, 3);
+        state.current_position = position;
+        state.cell_scale = 2;
+        state
+    }
+
+    #[test]
+    fn big_cells_respect_walls_and_floor() {
+        let tile_board = board_with(&[(5, 2)]);
+        //(4, 0)占棋盘的(8..9, 0..1)
+        assert!(has_no_tile(&[IVec2::new(4, 0)], 2, &tile_board));
+        assert!(!has_no_tile(&[IVec2::new(5, 0)], 2, &tile_board));
+        assert!(!has_no_tile(&[IVec2::new(-1, 0)], 2, &tile_board));
+        assert!(!has_no_tile(&[IVec2::new(0, -1)], 2, &tile_board));
+        //顶部以上视为空，(2, 1)占(4..5, 2..3)，与(5, 2)重叠
+        assert!(has_no_tile(&[IVec2::new(0, 10)], 2, &tile_board));
+        assert!(!has_no_tile(&[IVec2::new(2, 1)], 2, &tile_board));
+    }
+
+    #[test]
+    fn big_piece_stops_at_the_scaled_edges() {
+        let tile_board = board_with(&[]);
+        let state = big_o(IVec2::new(0, 0));
+        assert!(!can_move_left(&state, &tile_board));
+        assert!(!can_move_down(&state, &tile_board));
+        assert!(can_move_right(&state, &tile_board));
+        //O方块占两格，在第3格时右边缘已经到第9列
+        let state = big_o(IVec2::new(3, 4));
+        assert!(!can_move_right(&state, &tile_board));
+        assert!(can_move_down(&state, &tile_board));
+        assert!(!can_move_down(&big_o(IVec2::new(3, 4)), &board_with(&[(7, 7)])));
+    }
+
+    #[test]
+    fn big_piece_draws_scale_squared_tiles() {
+        let mut world = World::new();
+        world.insert_resource(big_o(IVec2::new(2, 0)));
+        world.insert_resource(TileBoard::new(10, 20, 1.0, 1.0));
+        world.insert_resource(Tiles(std::array::from_fn(|_| Sprite::default())));
+        world.insert_resource(NextState::<AppState>::default());
+        world.run_system_once(draw_piece).unwrap();
+        let expected: Vec<(u32, u32)> = (0..4).flat_map(|y| (4..8).map(move |x| (x, y))).collect();
+        assert_eq!(occupied(world.resource::<TileBoard>()), expected);
+    }
+
+    #[test]
+    fn big_double_row_clear_counts_as_one_line() {
+        let mut world = World::new();
+        //底部两行已满，O方块的上半部分在(8..9, 2..3)，另有一格在(0, 2)
+        let mut cells: Vec<(u32, u32)> = (0..2).flat_map(|y| (0..10).map(move |x| (x, y))).collect();
+        cells.extend([(8, 2), (9, 2), (8, 3), (9, 3), (0, 2)]);
+        let tile_board = spawn_board(&mut world, &cells);
+        world.insert_resource(tile_board);
+        world.insert_resource(big_o(IVec2::new(4, 0)));
+        world.insert_resource(stats::GameStats::default());
+        world.run_system_once(clear_lines).unwrap();
+        let game_stats = world.resource::<stats::GameStats>();
+        assert_eq!(game_stats.lines, 1);
+        assert_eq!(game_stats.clears, [1, 0, 0, 0]);
+        assert_eq!(occupied(world.resource::<TileBoard>()), [(0, 0), (8, 0), (9, 0), (8, 1), (9, 1)]);
+    }
+}
@@ -0,0 +1,232 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::ConfigData;
+use crate::game_logic::{AppState, TileBoard};
+use crate::modes::GameMode;
+use crate::scene::GameRng;
+
+//垃圾行方块的标记，消除时用来统计垃圾行
+#[derive(Component, Debug)]
+pub struct Garbage;
+
+//垃圾行使用的精灵
+#[derive(Resource)]
+pub struct GarbageTile(pub Sprite);
+
+//垃圾行的生成状态，使用单独的随机数，不影响方块序列
+#[derive(Resource, Debug)]
+pub struct GarbageState {
+    pub seed: u64,
+    pub rng: StdRng,
+    //上一行空洞所在的列
+    pub hole: Option<u32>,
+    //本局已经生成的垃圾行数
+    pub generated: u32,
+    //生存模式中距离上一次升起垃圾行的时间
+    pub rise_timer: f64,
+    //当前升起垃圾行的间隔，随时间缩短
+    pub rise_interval: f64,
+    //等待在下一个方块落定时升起的行数
+    pub pending: u32,
+}
+
+impl GarbageState {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            hole: None,
+            generated: 0,
+            rise_timer: 0.0,
+            rise_interval: 0.0,
+            pending: 0,
+        }
+    }
+
+    //生成下一行空洞的位置，messiness为空洞换列的概率
+    pub fn next_hole(&mut self, width: u32, messiness: f64) -> u32 {
+        let hole = match self.hole {
+            Some(last) if !self.rng.gen_bool(messiness.clamp(0.0, 1.0)) => last,
+            Some(last) => (last + self.rng.gen_range(1..width)) % width,
+            None => self.rng.gen_range(0..width),
+        };
+        self.hole = Some(hole);
+        self.generated += 1;
+        hole
+    }
+}
+
+impl Default for GarbageState {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+//把棋盘上所有方块向上移动，在底部插入带空洞的垃圾行，与clear_lines中的下移相反
+//被挤出顶部的方块会被删除，此时返回false表示堆到顶了
+pub fn push_garbage(
+    commands: &mut Commands,
+    tile_board: &mut TileBoard,
+    sprite: &Sprite,
+    holes: &[u32],
+) -> bool {
+    let rows = holes.len() as u32;
+    if rows == 0 {
+        return true;
+    }
+    let (width, height) = (tile_board.width, tile_board.height);
+    let mut fits = true;
+    for y in height.saturating_sub(rows)..height {
+        for x in 0..width {
+            if let Some(entity) = tile_board.remove(x, y) {
+                commands.entity(entity).despawn();
+                fits = false;
+            }
+        }
+    }
+    for y in (0..height.saturating_sub(rows)).rev() {
+        for x in 0..width {
+            if tile_board.entity_at(x, y).is_some() {
+                let (&entity, pos) = tile_board.swap_tile((x, y), (x, y + rows));
+                commands.entity(entity).insert(pos);
+            }
+        }
+    }
+    for (y, hole) in holes.iter().enumerate() {
+        for x in (0..width).filter(|x| x != hole) {
+            let entity = commands.spawn_empty().id();
+            let transform = tile_board.set(x, y as u32, entity);
+            commands.entity(entity).insert((sprite.clone(), transform, Garbage));
+        }
+    }
+    fits
+}
+
+//一行是否含有垃圾方块
+pub fn is_garbage_line(line: u32, tile_board: &TileBoard, garbage_query: &Query<(), With<Garbage>>) -> bool {
+    (0..tile_board.width).any(|x| tile_board.entity_at(x, line).is_some_and(|e| garbage_query.contains(*e)))
+}
+
+//挖掘模式中，棋盘上的垃圾行不足时从底部补充，直到生成的总行数达到目标
+fn refill_count(mode: GameMode, config: &ConfigData, state: &GarbageState, current: u32) -> u32 {
+    match mode {
+        GameMode::Dig(lines) => {
+            let missing = config.modes_config.garbage_height.saturating_sub(current);
+            missing.min(lines.saturating_sub(state.generated))
+        },
+        _ => 0,
+    }
+}
+
+fn generate_rows(
+    commands: &mut Commands,
+    tile_board: &mut TileBoard,
+    sprite: &Sprite,
+    state: &mut GarbageState,
+    config: &ConfigData,
+    count: u32,
+) -> bool {
+    let holes: Vec<u32> = (0..count)
+        .map(|_| state.next_hole(tile_board.width, config.modes_config.garbage_messiness))
+        .collect();
+    push_garbage(commands, tile_board, sprite, &holes)
+}
+
+//每局开始时重置垃圾行的随机数，配置了种子时使用固定的种子，并铺好初始的垃圾行
+pub fn setup_garbage(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    config: Res<ConfigData>,
+    rng: Res<GameRng>,
+    mut state: ResMut<GarbageState>,
+    mut tile_board: ResMut<TileBoard>,
+    sprite: Res<GarbageTile>,
+) {
+    let seed = match config.modes_config.garbage_seed {
+        0 => rng.seed,
+        seed => seed,
+    };
+    *state = GarbageState::new(seed);
+    state.rise_interval = config.modes_config.survival_interval;
+    let count = refill_count(*mode, &config, &state, 0);
+    generate_rows(&mut commands, &mut tile_board, &sprite.0, &mut state, &config, count);
+}
+
+//消除后补充垃圾行
+pub fn refill_garbage(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    config: Res<ConfigData>,
+    mut state: ResMut<GarbageState>,
+    mut tile_board: ResMut<TileBoard>,
+    sprite: Res<GarbageTile>,
+    garbage_query: Query<(), With<Garbage>>,
+) {
+    let current = (0..tile_board.height).filter(|y| is_garbage_line(*y, &tile_board, &garbage_query)).count() as u32;
+    let count = refill_count(*mode, &config, &state, current);
+    generate_rows(&mut commands, &mut tile_board, &sprite.0, &mut state, &config, count);
+}
+
+//生存模式中按计时器积累要升起的垃圾行，每升起一行间隔缩短一些
+pub fn tick_rising(
+    mode: Res<GameMode>,
+    config: Res<ConfigData>,
+    mut state: ResMut<GarbageState>,
+    time: Res<Time<Fixed>>,
+) {
+    if *mode != GameMode::Survival {
+        return;
+    }
+    state.rise_timer += time.delta_secs_f64();
+    if state.rise_timer >= state.rise_interval {
+        state.rise_timer -= state.rise_interval;
+        state.pending += 1;
+        let modes_config = &config.modes_config;
+        state.rise_interval = (state.rise_interval * modes_config.survival_acceleration).max(modes_config.survival_min_interval);
+    }
+}
+
+//方块落定后升起积累的垃圾行，方块被挤出顶部时游戏结束
+pub fn raise_garbage(
+    mut commands: Commands,
+    config: Res<ConfigData>,
+    mut state: ResMut<GarbageState>,
+    mut tile_board: ResMut<TileBoard>,
+    sprite: Res<GarbageTile>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if state.pending == 0 {
+        return;
+    }
+    let count = std::mem::take(&mut state.pending);
+    if !generate_rows(&mut commands, &mut tile_board, &sprite.0, &mut state, &config, count) {
+        next_state.set(AppState::DEAD);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::world::CommandQueue;
+
+    #[test]
+    fn garbage_pushes_onto_an_empty_board() {
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut tile_board = TileBoard::new(10, 20, 1.0, 1.0);
+
+        let mut commands = Commands::new(&mut queue, &world);
+        assert!(push_garbage(&mut commands, &mut tile_board, &Sprite::default(), &[3, 7]));
+        queue.apply(&mut world);
+
+        assert_eq!(tile_board.get_all_entitys().len(), 18);
+        assert!(tile_board.entity_at(3, 0).is_none());
+        assert!(tile_board.entity_at(7, 1).is_none());
+        assert!(tile_board.entity_at(0, 2).is_none());
+        for entity in tile_board.get_all_entitys() {
+            assert!(world.get::<Garbage>(entity).is_some());
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+
+use crate::config::ConfigData;
+use crate::modes::GameMode;
+use crate::puzzle::{puzzle_objective, PuzzlePack};
+use crate::scene::{self, Player};
+use crate::stats::{format_time, GameStats};
+use crate::versus::Versus;
+
+//HUD中显示的数据项
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub enum HudField {
+    Score,
+    Level,
+    Lines,
+    Time,
+    Pps,
+    Objective,
+}
+
+//显示顺序和标题
+const HUD_FIELDS: [(HudField, &str); 6] = [
+    (HudField::Score, "SCORE"),
+    (HudField::Level, "LEVEL"),
+    (HudField::Lines, "LINES"),
+    (HudField::Time, "TIME"),
+    (HudField::Pps, "PPS"),
+    (HudField::Objective, "GOAL"),
+];
+
+//HUD位于每个玩家游戏区域左侧，右对齐到边框外一格，从顶部往下排列
+fn hud_position(config: &ConfigData, player: usize, row: usize) -> Vec3 {
+    let tile = config.game_config.tile_size * config.game_config.scale_factor;
+    Vec3::new(-tile * 6.0 + scene::board_offset(player, tile), tile * (9.5 - row as f32 * 2.0), 1.0)
+}
+
+pub fn spawn_hud(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<ConfigData>,
+) {
+    spawn_fields(&mut commands, &asset_server, &config, 0);
+}
+
+//生成一个玩家的HUD，返回生成的实体，对战模式中第二个玩家的HUD随对战场景一起删除
+pub fn spawn_fields(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    config: &ConfigData,
+    player: usize,
+) -> Vec<Entity> {
+    let tile = config.game_config.tile_size * config.game_config.scale_factor;
+    let label_font = asset_server.load("fonts/FiraSans-Bold.ttf");
+    let value_font = asset_server.load("fonts/FiraMono-Medium.ttf");
+    let mut entities = vec![];
+    for (row, (field, label)) in HUD_FIELDS.iter().enumerate() {
+        let position = hud_position(config, player, row);
+        let label = commands.spawn((
+            Text2d::new(*label),
+            TextFont {
+                font: label_font.clone(),
+                font_size: tile * 0.6,
+                ..default()
+            },
+            TextColor(Color::srgb(0.7, 0.7, 0.7)),
+            Anchor::TopRight,
+            Transform::from_translation(position),
+        )).id();
+        let value = commands.spawn((
+            Text2d::default(),
+            TextFont {
+                font: value_font.clone(),
+                font_size: tile * 0.8,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            Anchor::TopRight,
+            Transform::from_translation(position - Vec3::new(0.0, tile * 0.7, 0.0)),
+            *field,
+            Player(player),
+        )).id();
+        entities.extend([label, value]);
+    }
+    entities
+}
+
+//每帧根据本局数据刷新HUD，第二个玩家的数据存放在对战状态中
+pub fn update_hud(
+    game_stats: Res<GameStats>,
+    versus: Res<Versus>,
+    mode: Res<GameMode>,
+    pack: Res<PuzzlePack>,
+    mut query: Query<(&mut Text2d, &HudField, &Player)>,
+) {
+    for (mut text, field, player) in query.iter_mut() {
+        let game_stats = match player.0 {
+            0 => &*game_stats,
+            _ => match versus.second_stats() {
+                Some(stats) => stats,
+                None => continue,
+            },
+        };
+        let value = match field {
+            HudField::Score => game_stats.score.to_string(),
+            HudField::Level => game_stats.level.to_string(),
+            HudField::Lines => game_stats.lines.to_string(),
+            HudField::Time => format_time(game_stats.elapsed),
+            HudField::Pps => format!("{:.2}", game_stats.pieces_per_second()),
+            HudField::Objective => match *mode {
+                GameMode::Puzzle(index) => puzzle_objective(&pack, index, game_stats),
+                _ => mode.objective(game_stats),
+            },
+        };
+        //内容不变时不修改，避免每帧重新排版文字
+        if text.0 != value {
+            text.0 = value;
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::config::{ConfigData, KeyCapture};
+use crate::keys;
+use crate::rules::Ruleset;
+
+//玩家操作，游戏逻辑只处理操作而不关心来自哪个输入设备
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Left,
+    Right,
+    Down,
+    Drop,
+    RotateLeft,
+    RotateRight,
+    Pause,
+    Restart,
+    QuickRestart,
+}
+
+impl Action {
+    //所有操作，顺序即设置界面中的显示顺序
+    pub const ALL: [Action; 9] = [
+        Action::Left,
+        Action::Right,
+        Action::Down,
+        Action::Drop,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Pause,
+        Action::Restart,
+        Action::QuickRestart,
+    ];
+
+    //可以长按重复的移动操作，顺序即同时按下时的优先级
+    pub const MOVES: [Action; 6] = [
+        Action::Left,
+        Action::Right,
+        Action::Down,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::Drop,
+    ];
+
+    //配置文件中使用的名字
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Down => "down",
+            Action::Drop => "drop",
+            Action::RotateLeft => "rotate_left",
+            Action::RotateRight => "rotate_right",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::QuickRestart => "quick_restart",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Left => "Move left",
+            Action::Right => "Move right",
+            Action::Down => "Soft drop",
+            Action::Drop => "Hard drop",
+            Action::RotateLeft => "Rotate left",
+            Action::RotateRight => "Rotate right",
+            Action::Pause => "Pause",
+            Action::Restart => "Restart",
+            Action::QuickRestart => "Quick restart",
+        }
+    }
+}
+
+//本次固定步长产生的操作，由process_input每步重新生成，供各个状态下的游戏系统读取
+#[derive(Resource, Debug, Default)]
+pub struct Actions(pub Vec<Action>);
+
+impl Actions {
+    pub fn contains(&self, action: Action) -> bool {
+        self.0.contains(&action)
+    }
+}
+
+//一次按下或松开，time为发生时的虚拟时间
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub action: Action,
+    pub pressed: bool,
+    pub time: Duration,
+}
+
+//按帧记录、按固定步长消费的输入缓冲，使输入处理与渲染帧率无关
+#[derive(Resource, Debug, Default)]
+pub struct InputBuffer {
+    events: VecDeque<InputEvent>,
+    //已记录为按下的操作，用于检测按下和松开
+    recorded: HashSet<Action>,
+    //上一次取出的事件中最后一次按下的时间
+    pub last_press: Option<Duration>,
+}
+
+impl InputBuffer {
+    //根据本帧的设备状态记录按下/松开，同一帧内按下又松开也会完整记录
+    pub fn record(&mut self, action: Action, down: bool, tapped: bool, time: Duration) {
+        let was_down = self.recorded.contains(&action);
+        if tapped && was_down {
+            self.events.push_back(InputEvent { action, pressed: false, time });
+        }
+        if tapped || (down && !was_down) {
+            self.events.push_back(InputEvent { action, pressed: true, time });
+        }
+        if !down && (tapped || was_down) {
+            self.events.push_back(InputEvent { action, pressed: false, time });
+        }
+        if down {
+            self.recorded.insert(action);
+        } else {
+            self.recorded.remove(&action);
+        }
+    }
+
+    //丢弃还未处理的按下事件，避免菜单中的按键带入新的一局
+    pub fn discard_presses(&mut self) {
+        self.events.retain(|e| !e.pressed);
+    }
+
+    //取出发生在now之前(含)的所有事件
+    pub fn drain_until(&mut self, now: Duration) -> Vec<InputEvent> {
+        let count = self.events.iter().take_while(|e| e.time <= now).count();
+        let events: Vec<InputEvent> = self.events.drain(..count).collect();
+        self.last_press = events.iter().rev().find(|e| e.pressed).map(|e| e.time);
+        events
+    }
+
+    //本步的按下实际发生在固定步长时间之前多少秒，本步没有按下时为0
+    pub fn press_lag(&self, now: Duration) -> f64 {
+        self.last_press.map_or(0.0, |time| now.saturating_sub(time).as_secs_f64())
+    }
+}
+
+//固定步长中的按键状态和长按重复(DAS/ARR)的计时，以固定步长的帧数计
+#[derive(Resource, Debug, Default)]
+pub struct RepeatState {
+    pub held: HashSet<Action>,
+    pub frame: u64,
+    pub key_press_start_frame: Option<u64>,
+    pub last_repeat_frame: u64,
+}
+
+//所有手柄摇杆合并后的方向，previous用于判断是否刚刚推动
+#[derive(Resource, Debug, Default)]
+pub struct StickState {
+    pub current: IVec2,
+    pub previous: IVec2,
+}
+
+//把摇杆位置转换为方向，只取绝对值较大的一个轴，避免斜推时同时触发两个方向
+pub fn stick_direction(stick: Vec2, deadzone: f32) -> IVec2 {
+    if stick.x.abs() < deadzone && stick.y.abs() < deadzone {
+        return IVec2::ZERO;
+    }
+    if stick.x.abs() >= stick.y.abs() {
+        IVec2::new(stick.x.signum() as i32, 0)
+    } else {
+        IVec2::new(0, stick.y.signum() as i32)
+    }
+}
+
+pub fn update_stick_state(
+    mut stick: ResMut<StickState>,
+    config: Res<ConfigData>,
+    gamepads: Query<&Gamepad>,
+) {
+    stick.previous = stick.current;
+    stick.current = gamepads.iter()
+        .map(|gamepad| stick_direction(gamepad.left_stick(), config.gamepad_config.stick_deadzone))
+        .find(|direction| *direction != IVec2::ZERO)
+        .unwrap_or(IVec2::ZERO);
+}
+
+//摇杆只控制左右移动和软降
+fn stick_matches(action: Action, direction: IVec2) -> bool {
+    match action {
+        Action::Left => direction.x < 0,
+        Action::Right => direction.x > 0,
+        Action::Down => direction.y < 0,
+        _ => false
+    }
+}
+
+//键盘、手柄按键或摇杆中任意一个刚刚按下
+pub fn just_pressed(
+    action: Action,
+    config: &ConfigData,
+    keyboard_input: &ButtonInput<KeyCode>,
+    gamepads: &[&Gamepad],
+    stick: &StickState,
+) -> bool {
+    if keyboard_input.just_pressed(keys::from_str(config.keys_config.get(action))) {
+        return true;
+    }
+    if let Some(button) = keys::gamepad_button_from_str(config.gamepad_config.buttons.get(action)) {
+        if gamepads.iter().any(|gamepad| gamepad.just_pressed(button)) {
+            return true;
+        }
+    }
+    stick_matches(action, stick.current) && !stick_matches(action, stick.previous)
+}
+
+//键盘、手柄按键或摇杆中任意一个处于按下状态
+pub fn pressed(
+    action: Action,
+    config: &ConfigData,
+    keyboard_input: &ButtonInput<KeyCode>,
+    gamepads: &[&Gamepad],
+    stick: &StickState,
+) -> bool {
+    if keyboard_input.pressed(keys::from_str(config.keys_config.get(action))) {
+        return true;
+    }
+    if let Some(button) = keys::gamepad_button_from_str(config.gamepad_config.buttons.get(action)) {
+        if gamepads.iter().any(|gamepad| gamepad.pressed(button)) {
+            return true;
+        }
+    }
+    stick_matches(action, stick.current)
+}
+
+//菜单导航，使用固定的按键，不受按键绑定影响
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+//系统中读取所有输入设备用的参数
+#[derive(SystemParam)]
+pub struct InputDevices<'w, 's> {
+    keyboard_input: Res<'w, ButtonInput<KeyCode>>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+    stick: Res<'w, StickState>,
+}
+
+impl InputDevices<'_, '_> {
+    pub fn just_pressed(&self, action: Action, config: &ConfigData) -> bool {
+        let gamepads: Vec<&Gamepad> = self.gamepads.iter().collect();
+        just_pressed(action, config, &self.keyboard_input, &gamepads, &self.stick)
+    }
+
+    pub fn pressed(&self, action: Action, config: &ConfigData) -> bool {
+        let gamepads: Vec<&Gamepad> = self.gamepads.iter().collect();
+        pressed(action, config, &self.keyboard_input, &gamepads, &self.stick)
+    }
+
+    //本帧的菜单操作：方向键/WASD或十字键/摇杆移动，Enter/空格或South确认，Escape或East返回
+    pub fn menu_input(&self) -> Option<MenuInput> {
+        let keyboard = &self.keyboard_input;
+        let gamepad_pressed = |button| self.gamepads.iter().any(|gamepad| gamepad.just_pressed(button));
+        let stick_pushed = |y: i32| self.stick.current.y == y && self.stick.previous.y != y;
+        let stick_pushed_x = |x: i32| self.stick.current.x == x && self.stick.previous.x != x;
+        if keyboard.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) || gamepad_pressed(GamepadButton::DPadUp) || stick_pushed(1) {
+            Some(MenuInput::Up)
+        } else if keyboard.any_just_pressed([KeyCode::ArrowDown, KeyCode::KeyS]) || gamepad_pressed(GamepadButton::DPadDown) || stick_pushed(-1) {
+            Some(MenuInput::Down)
+        } else if keyboard.any_just_pressed([KeyCode::ArrowLeft, KeyCode::KeyA]) || gamepad_pressed(GamepadButton::DPadLeft) || stick_pushed_x(-1) {
+            Some(MenuInput::Left)
+        } else if keyboard.any_just_pressed([KeyCode::ArrowRight, KeyCode::KeyD]) || gamepad_pressed(GamepadButton::DPadRight) || stick_pushed_x(1) {
+            Some(MenuInput::Right)
+        } else if keyboard.any_just_pressed([KeyCode::Enter, KeyCode::Space]) || gamepad_pressed(GamepadButton::South) || gamepad_pressed(GamepadButton::Start) {
+            Some(MenuInput::Confirm)
+        } else if keyboard.any_just_pressed([KeyCode::Escape, KeyCode::Backspace]) || gamepad_pressed(GamepadButton::East) {
+            Some(MenuInput::Back)
+        } else {
+            None
+        }
+    }
+}
+
+//在每帧开始时把输入设备的状态记录到缓冲中
+pub fn record_input(
+    devices: InputDevices,
+    config: Res<ConfigData>,
+    capture: Res<KeyCapture>,
+    time: Res<Time<Virtual>>,
+    mut buffer: ResMut<InputBuffer>,
+) {
+    //正在设置按键时视为全部松开，不产生任何操作
+    let capturing = capture.action.is_some();
+    for action in Action::ALL {
+        let down = !capturing && devices.pressed(action, &config);
+        let tapped = !capturing && devices.just_pressed(action, &config);
+        buffer.record(action, down, tapped, time.elapsed());
+    }
+}
+
+//在固定步长中消费已到时的输入，转换为本步的操作，包括首次按下和长按重复
+pub fn process_input(
+    config: Res<ConfigData>,
+    rules: Res<Ruleset>,
+    time: Res<Time<Fixed>>,
+    mut buffer: ResMut<InputBuffer>,
+    mut repeat: ResMut<RepeatState>,
+    mut actions: ResMut<Actions>,
+) {
+    actions.0.clear();
+    repeat.frame += 1;
+    let current_frame = repeat.frame;
+
+    let mut new_press = false;
+    for event in buffer.drain_until(time.elapsed()) {
+        if !event.pressed {
+            repeat.held.remove(&event.action);
+            continue;
+        }
+        repeat.held.insert(event.action);
+        actions.0.push(event.action);
+        if Action::MOVES.contains(&event.action) {
+            // 记录按键开始时间和上次重复时间
+            repeat.key_press_start_frame = Some(current_frame);
+            repeat.last_repeat_frame = current_frame;
+            new_press = true;
+        }
+    }
+    if new_press {
+        return;
+    }
+
+    // 如果没有按键开始记录，直接返回
+    let Some(start_frame) = repeat.key_press_start_frame else {
+        return;
+    };
+    // 检查哪些键还在按下，如果没有按键按下，清除重复状态
+    let Some(action) = Action::MOVES.into_iter().find(|a| repeat.held.contains(a)) else {
+        repeat.key_press_start_frame = None;
+        return;
+    };
+    // 规则可以覆盖配置文件中的重复延迟
+    let (first_repeat_delay, repeat_delay) = rules.das(&config.game_config);
+    // 检查是否已经过了首次重复延迟
+    if current_frame - start_frame < first_repeat_delay as u64 {
+        return;
+    }
+    // 检查是否到了重复时间
+    if current_frame - repeat.last_repeat_frame < repeat_delay as u64 {
+        return;
+    }
+    actions.0.push(action);
+    repeat.last_repeat_frame = current_frame;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad_with(buttons: &[GamepadButton], stick: Vec2) -> Gamepad {
+        let mut gamepad = Gamepad::default();
+        for button in buttons {
+            gamepad.digital_mut().press(*button);
+        }
+        gamepad.analog_mut().set(GamepadAxis::LeftStickX, stick.x);
+        gamepad.analog_mut().set(GamepadAxis::LeftStickY, stick.y);
+        gamepad
+    }
+
+    #[test]
+    fn dpad_press_maps_to_action() {
+        let config = ConfigData::new();
+        let keyboard = ButtonInput::<KeyCode>::default();
+        let gamepad = pad_with(&[GamepadButton::DPadLeft], Vec2::ZERO);
+        let stick = StickState::default();
+
+        assert!(just_pressed(Action::Left, &config, &keyboard, &[&gamepad], &stick));
+        assert!(pressed(Action::Left, &config, &keyboard, &[&gamepad], &stick));
+        assert!(!pressed(Action::Right, &config, &keyboard, &[&gamepad], &stick));
+    }
+
+    #[test]
+    fn held_button_is_not_just_pressed_next_frame() {
+        let config = ConfigData::new();
+        let keyboard = ButtonInput::<KeyCode>::default();
+        let mut gamepad = pad_with(&[GamepadButton::South], Vec2::ZERO);
+        //清除just_pressed，模拟按住到下一帧
+        gamepad.digital_mut().clear();
+        let stick = StickState::default();
+
+        assert!(!just_pressed(Action::RotateLeft, &config, &keyboard, &[&gamepad], &stick));
+        assert!(pressed(Action::RotateLeft, &config, &keyboard, &[&gamepad], &stick));
+    }
+
+    #[test]
+    fn stick_respects_deadzone() {
+        assert_eq!(stick_direction(Vec2::new(0.3, -0.2), 0.5), IVec2::ZERO);
+        assert_eq!(stick_direction(Vec2::new(-0.8, 0.1), 0.5), IVec2::new(-1, 0));
+        assert_eq!(stick_direction(Vec2::new(0.6, -0.9), 0.5), IVec2::new(0, -1));
+    }
+
+    #[test]
+    fn tap_within_one_frame_records_press_and_release() {
+        let mut buffer = InputBuffer::default();
+        buffer.record(Action::Left, false, true, Duration::from_millis(5));
+
+        let events = buffer.drain_until(Duration::from_millis(5));
+        assert_eq!(events.len(), 2);
+        assert!(events[0].pressed);
+        assert!(!events[1].pressed);
+    }
+
+    #[test]
+    fn events_wait_for_their_fixed_step() {
+        let mut buffer = InputBuffer::default();
+        buffer.record(Action::RotateRight, true, true, Duration::from_millis(10));
+        buffer.record(Action::RotateRight, false, false, Duration::from_millis(30));
+
+        assert!(buffer.drain_until(Duration::from_millis(0)).is_empty());
+        let first = buffer.drain_until(Duration::from_millis(16));
+        assert_eq!(first, vec![InputEvent { action: Action::RotateRight, pressed: true, time: Duration::from_millis(10) }]);
+        let second = buffer.drain_until(Duration::from_millis(33));
+        assert_eq!(second.len(), 1);
+        assert!(!second[0].pressed);
+    }
+
+    #[test]
+    fn press_lag_measures_from_the_last_press() {
+        let mut buffer = InputBuffer::default();
+        buffer.record(Action::Left, true, true, Duration::from_millis(20));
+        buffer.record(Action::Drop, true, true, Duration::from_millis(25));
+
+        buffer.drain_until(Duration::from_millis(33));
+        assert_eq!(buffer.last_press, Some(Duration::from_millis(25)));
+        assert!((buffer.press_lag(Duration::from_millis(33)) - 0.008).abs() < 1e-9);
+        buffer.drain_until(Duration::from_millis(50));
+        assert_eq!(buffer.press_lag(Duration::from_millis(50)), 0.0);
+    }
+
+    #[test]
+    fn stick_push_is_edge_triggered() {
+        let config = ConfigData::new();
+        let keyboard = ButtonInput::<KeyCode>::default();
+        let gamepad = pad_with(&[], Vec2::new(0.9, 0.0));
+        let mut stick = StickState { current: IVec2::new(1, 0), previous: IVec2::ZERO };
+
+        assert!(just_pressed(Action::Right, &config, &keyboard, &[&gamepad], &stick));
+        stick.previous = stick.current;
+        assert!(!just_pressed(Action::Right, &config, &keyboard, &[&gamepad], &stick));
+        assert!(pressed(Action::Right, &config, &keyboard, &[&gamepad], &stick));
+    }
+}
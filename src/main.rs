@@ -2,7 +2,19 @@ mod tetromino;
 mod config;
 mod scene;
 mod keys;
+mod input;
 mod game_logic;
+mod stats;
+mod modes;
+mod menu;
+mod replay;
+mod hud;
+mod garbage;
+mod rotation;
+mod rules;
+mod puzzle;
+mod fading;
+mod versus;
 
 use bevy::{
     prelude::*
@@ -41,38 +53,124 @@ fn main() {
         }));
     }
 
-    app.insert_state(game_logic::AppState::RUNNING);
-    app.insert_resource(config::ConfigData::new());
-    app.insert_resource(scene::init_game_state());
+    app.insert_state(game_logic::AppState::LOADING);
+    app.insert_resource(config::load_config(config::CONFIG_PATH));
+    app.init_resource::<config::KeyCapture>();
+    app.init_resource::<input::StickState>();
+    app.init_resource::<input::RepeatState>();
+    app.init_resource::<input::Actions>();
+    app.init_resource::<input::InputBuffer>();
+    app.init_resource::<scene::GameRng>();
+    app.insert_resource(scene::init_game_state(&mut rand::thread_rng()));
+    app.init_resource::<stats::GameStats>();
+    app.init_resource::<modes::GameMode>();
+    app.init_resource::<menu::MenuCursor>();
+    app.init_resource::<menu::SettingsReturn>();
+    app.init_resource::<replay::Replay>();
+    app.init_resource::<garbage::GarbageState>();
+    app.init_resource::<rules::Ruleset>();
+    app.init_resource::<fading::StackReveal>();
+    app.init_resource::<versus::Versus>();
+    app.insert_resource(rules::load_rulesets(rules::RULES_DIR));
+    app.insert_resource(puzzle::load_puzzle_pack(puzzle::PUZZLE_PACK_PATH, puzzle::PUZZLE_PROGRESS_PATH));
+    app.insert_resource(stats::load_records(stats::RECORDS_PATH));
     // app.add_plugins(FrameTimeDiagnosticsPlugin);
     app.add_plugins(EguiPlugin);
-    app.add_systems(Startup, (game_logic::init_scene, game_logic::spawn, game_logic::draw_piece).chain());
+    app.add_systems(Startup, (game_logic::init_scene, hud::spawn_hud, menu::open_main_menu).chain());
+    app.add_systems(Update, (hud::update_hud, fading::update_stack_alpha, versus::update_meters));
+    //主菜单、模式选择和设置界面
+    app.add_systems(OnEnter(game_logic::AppState::MAINMENU), menu::reset_cursor);
+    app.add_systems(OnEnter(game_logic::AppState::MODESELECT), menu::reset_cursor);
+    app.add_systems(Update, menu::main_menu.run_if(in_state(game_logic::AppState::MAINMENU)));
+    app.add_systems(Update, menu::mode_select.run_if(in_state(game_logic::AppState::MODESELECT)));
+    //暂停菜单，暂停时隐藏棋盘，倒计时开始时重新显示
+    app.add_systems(OnEnter(game_logic::AppState::PAUSED), (game_logic::hide_board, versus::hide_second_board, menu::reset_cursor));
+    app.add_systems(OnEnter(game_logic::AppState::COUNTDOWN), (game_logic::show_board, versus::show_second_board));
+    app.add_systems(OnTransition { exited: game_logic::AppState::RUNNING, entered: game_logic::AppState::PAUSED }, stats::count_pause);
+    app.add_systems(Update, menu::pause_menu.run_if(in_state(game_logic::AppState::PAUSED)));
+    //游戏结束界面
+    app.add_systems(OnEnter(game_logic::AppState::DEAD), (stats::finish_game, menu::reset_cursor));
+    app.add_systems(OnEnter(game_logic::AppState::FINISHED), (stats::finish_game, menu::reset_cursor));
+    app.add_systems(Update, menu::game_over_menu.run_if(in_state(game_logic::AppState::DEAD).or(in_state(game_logic::AppState::FINISHED))));
+    //每局开始时完整重建游戏状态，然后生成第一个方块，对战模式中每个棋盘各执行一次
+    app.add_systems(OnEnter(game_logic::AppState::STARTING), (
+        versus::reset_versus,
+        versus::spawn_versus_scene,
+        versus::start_boards,
+        game_logic::enter_running,
+    ).chain());
+    app.add_systems(versus::BoardStart, (
+        game_logic::reset_game,
+        puzzle::select_puzzle,
+        modes::setup_mode,
+        stats::resume_zen,
+        game_logic::update_preview_frames,
+        garbage::setup_garbage,
+        puzzle::fill_puzzle_board,
+        fading::reset_reveal,
+        replay::start_recording,
+        game_logic::spawn,
+        game_logic::draw_previews,
+        game_logic::draw_piece,
+    ).chain());
+    app.add_systems(OnExit(game_logic::AppState::RUNNING), (game_logic::stop_key_repeat, versus::stop_second_repeat, stats::save_zen));
+    //游戏中直接关闭窗口时不会离开RUNNING状态，退出前也保存一次
+    app.add_systems(Last, stats::save_zen.run_if(on_event::<AppExit>).run_if(in_state(game_logic::AppState::RUNNING)));
     // app.add_systems(Update, game_logic::text_update_system);
-    app.add_systems(Update, config::config_setting_panel);
+    app.add_systems(Update, (config::config_setting_panel, config::capture_key).chain().run_if(in_state(game_logic::AppState::SETTINGS)));
     
-    // 输入处理系统需要在Update中运行，确保不会错过按键事件
-    app.add_systems(Update, (
-        game_logic::handler_key_down,
-        game_logic::handler_key_repeat,
-    ).run_if(in_state(game_logic::AppState::RUNNING)));
+    // 每帧记录带时间戳的输入，确保不会错过按键事件，再在固定步长中按时间顺序消费
+    app.add_systems(PreUpdate, (
+        input::update_stick_state,
+        input::record_input,
+        versus::record_second_input,
+    ).chain().after(bevy::input::InputSystem));
+    app.add_systems(FixedUpdate, input::process_input);
+    //第二个棋盘的输入在换入后处理
+    app.add_systems(versus::BoardInput, input::process_input);
     
-    // 游戏逻辑系统使用固定更新频率，设置120fps
-    app.add_systems(FixedUpdate, (
+    // 游戏逻辑系统使用固定更新频率，设置120fps，对战模式中两个棋盘依次运行
+    app.add_systems(FixedUpdate, versus::tick_boards.after(input::process_input).run_if(in_state(game_logic::AppState::RUNNING)));
+    app.add_systems(versus::BoardTick, (
+        game_logic::handle_actions,
         game_logic::update_timer,
-        game_logic::remove_piece,
-        game_logic::step_down,
-        game_logic::draw_piece,
+        garbage::tick_rising,
+        (
+            game_logic::remove_piece,
+            game_logic::step_down,
+            game_logic::draw_piece,
+        ).chain().run_if(game_logic::piece_active),
         (
+            fading::mark_locked,
             game_logic::clear_lines,
+            game_logic::cascade_lines,
+            fading::reveal_on_clear,
+            puzzle::check_puzzle,
+            garbage::refill_garbage,
+            versus::exchange_garbage,
+            garbage::raise_garbage,
             // game_logic::print_board,
+            game_logic::start_entry_delay,
+        ).chain().run_if(game_logic::hit_bottom),
+        //出块延迟结束后生成下一个方块
+        (
+            game_logic::clear_top_out,
             game_logic::spawn,
+            game_logic::draw_previews,
             game_logic::draw_piece,
-        ).chain().run_if(game_logic::hit_bottom),
-    ).chain().run_if(in_state(game_logic::AppState::RUNNING)));
+        ).chain().run_if(game_logic::entry_ready),
+        modes::check_goal,
+    ).chain());
     
     // 配置固定更新频率为120fps (1/120 = 0.008333...秒)
     app.insert_resource(Time::<Fixed>::from_seconds(1.0 / 60.0));
-    app.add_systems(Update, game_logic::resume.run_if(in_state(game_logic::AppState::PAUSED)));
-    app.add_systems(Update, game_logic::reinit.run_if(in_state(game_logic::AppState::DEAD)));
+    app.add_systems(FixedUpdate, game_logic::resume.after(input::process_input).run_if(in_state(game_logic::AppState::PAUSED)));
+    //在棋盘运行之前记录，操作按执行时的帧号保存，帧号在棋盘运行的最后加一
+    app.add_systems(FixedUpdate, replay::record_actions.after(input::process_input).before(versus::tick_boards).run_if(in_state(game_logic::AppState::RUNNING)));
+    app.add_systems(FixedUpdate, game_logic::quick_restart.after(input::process_input));
+    app.add_systems(Update, game_logic::pause_on_focus_loss);
+    app.add_systems(OnEnter(game_logic::AppState::COUNTDOWN), game_logic::start_countdown);
+    app.add_systems(FixedUpdate, game_logic::update_countdown.after(input::process_input).run_if(in_state(game_logic::AppState::COUNTDOWN)));
+    app.add_systems(OnExit(game_logic::AppState::COUNTDOWN), game_logic::end_countdown);
     app.run();
 }
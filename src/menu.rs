@@ -0,0 +1,326 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+use crate::config::ConfigData;
+use crate::game_logic::AppState;
+use crate::input::{Action, InputDevices, MenuInput};
+use crate::modes::{master_grade, GameMode};
+use crate::replay::{Replay, REPLAY_DIR};
+use crate::rules::Rulesets;
+use crate::stats::{format_time, GameStats, GameSummary, SPLIT_LINES};
+
+//菜单中当前选中的项
+#[derive(Resource, Debug, Default)]
+pub struct MenuCursor(pub usize);
+
+//设置界面返回时进入的状态，从主菜单或暂停菜单打开
+#[derive(Resource, Debug)]
+pub struct SettingsReturn(pub AppState);
+
+impl Default for SettingsReturn {
+    fn default() -> Self {
+        Self(AppState::MAINMENU)
+    }
+}
+
+const MAIN_MENU_ITEMS: [&str; 3] = ["Play", "Settings", "Quit"];
+const GAME_OVER_ITEMS: [&str; 3] = ["Retry", "Save replay", "Back to menu"];
+const PAUSE_ITEMS: [&str; 4] = ["Resume", "Restart", "Settings", "Quit to menu"];
+
+pub fn open_main_menu(
+    mut next_state: ResMut<NextState<AppState>>
+) {
+    next_state.set(AppState::MAINMENU);
+}
+
+//进入新的菜单时光标回到第一项
+pub fn reset_cursor(
+    mut cursor: ResMut<MenuCursor>
+) {
+    cursor.0 = 0;
+}
+
+//根据菜单输入移动光标，确认时返回选中的项
+fn navigate(cursor: &mut usize, len: usize, input: Option<MenuInput>) -> Option<usize> {
+    match input {
+        Some(MenuInput::Up) => {
+            *cursor = (*cursor + len - 1) % len;
+            None
+        },
+        Some(MenuInput::Down) => {
+            *cursor = (*cursor + 1) % len;
+            None
+        },
+        Some(MenuInput::Confirm) => Some(*cursor),
+        _ => None
+    }
+}
+
+//在屏幕中央绘制菜单，返回鼠标点击的项
+fn show_menu(
+    ctx: &egui::Context,
+    title: &str,
+    items: &[&str],
+    cursor: usize,
+    add_contents: impl FnOnce(&mut egui::Ui),
+) -> Option<usize> {
+    let mut clicked = None;
+    egui::Window::new(title)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.vertical_centered_justified(|ui| {
+                for (i, item) in items.iter().enumerate() {
+                    let button = egui::Button::new(*item)
+                        .selected(i == cursor)
+                        .min_size(egui::vec2(240.0, 36.0));
+                    if ui.add(button).clicked() {
+                        clicked = Some(i);
+                    }
+                }
+                add_contents(ui);
+            });
+        });
+    clicked
+}
+
+pub fn main_menu(
+    mut contexts: EguiContexts,
+    devices: InputDevices,
+    mut cursor: ResMut<MenuCursor>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut settings_return: ResMut<SettingsReturn>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let mut chosen = navigate(&mut cursor.0, MAIN_MENU_ITEMS.len(), devices.menu_input());
+    if let Some(i) = show_menu(contexts.ctx_mut(), "Tetris", &MAIN_MENU_ITEMS, cursor.0, |_| {}) {
+        chosen = Some(i);
+    }
+    match chosen {
+        Some(0) => next_state.set(AppState::MODESELECT),
+        Some(1) => {
+            settings_return.0 = AppState::MAINMENU;
+            next_state.set(AppState::SETTINGS);
+        },
+        Some(2) => {
+            exit.send(AppExit::Success);
+        },
+        _ => {}
+    }
+}
+
+//选择游戏模式后开始新的一局，最后一项为返回，左右键切换本局使用的规则
+pub fn mode_select(
+    mut contexts: EguiContexts,
+    devices: InputDevices,
+    mut cursor: ResMut<MenuCursor>,
+    mut mode: ResMut<GameMode>,
+    mut rulesets: ResMut<Rulesets>,
+    config: Res<ConfigData>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let mut items: Vec<&str> = GameMode::ALL.iter().map(|m| m.label()).collect();
+    items.push("Back");
+
+    let input = devices.menu_input();
+    if input == Some(MenuInput::Back) {
+        next_state.set(AppState::MAINMENU);
+        return;
+    }
+    match input {
+        Some(MenuInput::Left) => rulesets.cycle(-1),
+        Some(MenuInput::Right) => rulesets.cycle(1),
+        _ => {}
+    }
+    let mut chosen = navigate(&mut cursor.0, items.len(), input);
+    let description = GameMode::ALL.get(cursor.0).map(|m| m.description()).unwrap_or("");
+    let rotation = GameMode::ALL.get(cursor.0).map(|m| {
+        let rules = rulesets.for_mode(*m);
+        let source = if rulesets.selected.is_some() && !m.fixed_rules() { "selected" } else { "mode default" };
+        format!("Rules: {} ({} rotation, {})", rules.name, rules.rotation.label(), source)
+    });
+    let mut step = 0;
+    if let Some(i) = show_menu(contexts.ctx_mut(), "Select mode", &items, cursor.0, |ui| {
+        ui.separator();
+        ui.label(description);
+        if let Some(rotation) = &rotation {
+            ui.horizontal(|ui| {
+                if ui.button("<").clicked() {
+                    step = -1;
+                }
+                ui.label(rotation);
+                if ui.button(">").clicked() {
+                    step = 1;
+                }
+            });
+        }
+    }) {
+        chosen = Some(i);
+    }
+    if step != 0 {
+        rulesets.cycle(step);
+    }
+    match chosen {
+        Some(i) if i < GameMode::ALL.len() => {
+            *mode = GameMode::ALL[i].with_config(&config.modes_config);
+            next_state.set(AppState::STARTING);
+        },
+        Some(_) => next_state.set(AppState::MAINMENU),
+        None => {}
+    }
+}
+
+//暂停菜单：继续(倒计时后恢复)、重新开始、设置或回到主菜单，返回键等同于继续
+pub fn pause_menu(
+    mut contexts: EguiContexts,
+    devices: InputDevices,
+    mut cursor: ResMut<MenuCursor>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut settings_return: ResMut<SettingsReturn>,
+) {
+    let input = devices.menu_input();
+    let mut chosen = if input == Some(MenuInput::Back) {
+        Some(0)
+    } else {
+        navigate(&mut cursor.0, PAUSE_ITEMS.len(), input)
+    };
+    if let Some(i) = show_menu(contexts.ctx_mut(), "Paused", &PAUSE_ITEMS, cursor.0, |_| {}) {
+        chosen = Some(i);
+    }
+    match chosen {
+        Some(0) => next_state.set(AppState::COUNTDOWN),
+        Some(1) => next_state.set(AppState::STARTING),
+        Some(2) => {
+            settings_return.0 = AppState::PAUSED;
+            next_state.set(AppState::SETTINGS);
+        },
+        Some(3) => next_state.set(AppState::MAINMENU),
+        _ => {}
+    }
+}
+
+//游戏结束界面：显示本局成绩和个人最佳，可以重玩、保存回放或回到主菜单
+pub fn game_over_menu(
+    mut contexts: EguiContexts,
+    devices: InputDevices,
+    config: Res<ConfigData>,
+    mut cursor: ResMut<MenuCursor>,
+    mut summary: ResMut<GameSummary>,
+    replay: Res<Replay>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let input = devices.menu_input();
+    if input == Some(MenuInput::Back) {
+        next_state.set(AppState::MAINMENU);
+        return;
+    }
+    //对战的回放只有一个棋盘的操作，无法还原垃圾行的交换，所以不提供保存
+    let versus = summary.mode == GameMode::Versus;
+    let offered: Vec<usize> = (0..GAME_OVER_ITEMS.len()).filter(|i| !(versus && *i == 1)).collect();
+    let mut chosen = navigate(&mut cursor.0, offered.len(), input).and_then(|i| offered.get(i).copied());
+    //重新开始键直接重玩，菜单确认优先，避免与Enter冲突
+    if chosen.is_none() && devices.just_pressed(Action::Restart, &config) {
+        chosen = Some(0);
+    }
+
+    let stats = &summary.stats;
+    let best_text = if summary.new_best {
+        "New personal best!".to_string()
+    } else {
+        match &summary.previous_best {
+            Some(best) => format!("Personal best: {} points, {} lines, {}", best.score, best.lines, format_time(best.time)),
+            None => String::new(),
+        }
+    };
+    //对战模式中分别显示两个玩家的成绩
+    let players: Vec<(Option<String>, &GameStats)> = match &summary.second_stats {
+        Some(second) => vec![(Some("Player 1".to_string()), stats), (Some("Player 2".to_string()), second)],
+        None => vec![(None, stats)],
+    };
+    let grade = (summary.mode == GameMode::Master).then(|| format!("Grade: {}", master_grade(stats)));
+    let chain = (stats.max_chain > 0).then(|| format!("Best chain: {}", stats.max_chain));
+    let sent = versus.then(|| {
+        format!("Garbage sent: P1 {}  P2 {}", summary.garbage_sent[0], summary.garbage_sent[1])
+    });
+    let title = match (summary.completed, summary.mode) {
+        (true, GameMode::Ultra(_)) => "Time up",
+        (true, GameMode::Marathon(_)) => "Victory!",
+        (true, GameMode::Puzzle(_)) => "Solved!",
+        (true, _) => "Finished",
+        (false, GameMode::Puzzle(_)) => "Failed",
+        (false, GameMode::Versus) if summary.winner == Some(0) => "Player 1 wins!",
+        (false, GameMode::Versus) => "Player 2 wins!",
+        (false, _) => "Game over",
+    };
+    //谜题完成后重玩进入下一个谜题
+    let items: Vec<&str> = offered.iter().map(|i| match i {
+        0 if summary.completed && matches!(summary.mode, GameMode::Puzzle(_)) => "Next puzzle",
+        _ => GAME_OVER_ITEMS[*i],
+    }).collect();
+    let replay_text = summary.replay_path.as_ref().map(|path| format!("Replay saved to {}", path));
+    if let Some(i) = show_menu(contexts.ctx_mut(), title, &items, cursor.0, |ui| {
+        ui.separator();
+        ui.label(format!("Mode: {}", summary.mode.label()));
+        for (header, stats) in players.iter() {
+            if let Some(header) = header {
+                ui.separator();
+                ui.strong(header);
+            }
+            for line in stats_lines(stats).iter() {
+                ui.label(line);
+            }
+            if !stats.splits.is_empty() {
+                ui.separator();
+                ui.label("Splits");
+                for (i, time) in stats.splits.iter().enumerate() {
+                    ui.monospace(format!("{} lines: {}", (i as u32 + 1) * SPLIT_LINES, format_time(*time)));
+                }
+            }
+        }
+        if let Some(grade) = &grade {
+            ui.strong(grade);
+        }
+        if let Some(chain) = &chain {
+            ui.strong(chain);
+        }
+        if let Some(sent) = &sent {
+            ui.strong(sent);
+        }
+        if !best_text.is_empty() {
+            ui.strong(&best_text);
+        }
+        if let Some(text) = &replay_text {
+            ui.label(text);
+        }
+    }) {
+        chosen = offered.get(i).copied();
+    }
+    match chosen {
+        Some(0) => next_state.set(AppState::STARTING),
+        Some(1) => match replay.save(REPLAY_DIR) {
+            Ok(path) => summary.replay_path = Some(path),
+            Err(err) => error!("failed to save replay: {}", err),
+        },
+        Some(2) => next_state.set(AppState::MAINMENU),
+        _ => {}
+    }
+}
+
+//结束界面中一个玩家的成绩
+fn stats_lines(stats: &GameStats) -> [String; 8] {
+    [
+        format!("Score: {}", stats.score),
+        format!("Lines: {}", stats.lines),
+        format!("Level: {}", stats.level),
+        format!("Time: {}", format_time(stats.elapsed)),
+        format!("Pieces per second: {:.2}", stats.pieces_per_second()),
+        format!("Pauses: {}", stats.pauses),
+        format!(
+            "Singles: {}  Doubles: {}  Triples: {}  Tetrises: {}",
+            stats.clears[0], stats.clears[1], stats.clears[2], stats.clears[3]
+        ),
+        format!("T-spins: {}  Perfect clears: {}", stats.tspins.iter().sum::<u32>(), stats.perfect_clears),
+    ]
+}
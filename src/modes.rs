@@ -0,0 +1,372 @@
+use bevy::prelude::*;
+
+use crate::config::{ConfigData, ModesConfig};
+use crate::game_logic::AppState;
+use crate::input::InputBuffer;
+use crate::rules::{Ruleset, Rulesets};
+use crate::scene::GameState;
+use crate::stats::{format_time, GameStats, Record};
+
+//竞速模式默认的目标行数
+pub const DEFAULT_SPRINT_LINES: u32 = 40;
+//限时模式默认的时长(秒)
+pub const DEFAULT_ULTRA_SECONDS: u32 = 120;
+//马拉松模式默认的目标行数，0表示无尽
+pub const DEFAULT_MARATHON_LINES: u32 = 150;
+//马拉松模式可选的目标行数
+pub const MARATHON_GOALS: [u32; 3] = [150, 200, 0];
+//无尽马拉松的最高等级
+const MARATHON_ENDLESS_MAX_LEVEL: u32 = 20;
+//挖掘模式默认需要消除的垃圾行数
+pub const DEFAULT_DIG_LINES: u32 = 18;
+//渐隐模式中方块落定后开始淡出的秒数
+const FADING_DELAY: f64 = 3.0;
+//大师模式的最高等级
+pub const MASTER_MAX_LEVEL: u32 = 999;
+//段位及需要的分数，从9级到S9
+const MASTER_GRADES: [(&str, u32); 18] = [
+    ("9", 0),
+    ("8", 400),
+    ("7", 800),
+    ("6", 1400),
+    ("5", 2000),
+    ("4", 3500),
+    ("3", 5500),
+    ("2", 8000),
+    ("1", 12000),
+    ("S1", 16000),
+    ("S2", 22000),
+    ("S3", 30000),
+    ("S4", 40000),
+    ("S5", 52000),
+    ("S6", 66000),
+    ("S7", 82000),
+    ("S8", 100000),
+    ("S9", 120000),
+];
+//获得GM需要通过的检查点：等级、最长用时(秒)、最低分数
+pub const GM_CHECKPOINTS: [(u32, f64, u32); 3] = [
+    (300, 255.0, 12000),
+    (500, 450.0, 40000),
+    (999, 810.0, 126000),
+];
+//游戏模式，在模式选择界面中选择，每局开始时决定规则和结束条件
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum GameMode {
+    //无尽模式，直到方块堆到顶
+    #[default]
+    Endless,
+    //竞速模式，消除指定行数后结束，比较用时
+    Sprint(u32),
+    //限时模式，时间到后结束，比较分数
+    Ultra(u32),
+    //马拉松模式，等级决定下落速度，消除目标行数后胜利，0为无尽
+    Marathon(u32),
+    //挖掘模式，消除指定数量的垃圾行后结束，比较用时
+    Dig(u32),
+    //生存模式，垃圾行从底部不断升起且越来越快，比较坚持的时间
+    Survival,
+    //大师模式，20G重力，按分数、用时和每段的表现评定段位
+    Master,
+    //经典模式，使用NES的规则
+    Classic,
+    //禅模式，不会结束，堆到顶时清除最上面几行，统计数据跨局累计
+    Zen,
+    //谜题模式，用固定的方块序列在给定的棋盘上完成目标，参数为谜题包中的序号
+    Puzzle(u32),
+    //渐隐模式，落定的方块过几秒后淡出，消行时短暂显示
+    Fading,
+    //隐形模式，落定的方块立即隐藏，消行时短暂显示
+    Invisible,
+    //大方块模式，每个方块格子占2x2，在5x10的格子上移动
+    Big,
+    //连锁模式，消行后相连的方块各自下落，可以引发连锁消除
+    Cascade,
+    //双人对战，两个棋盘并排，消行时向对手发送垃圾行，先堆到顶的一方输
+    Versus,
+}
+
+impl GameMode {
+    //模式选择界面中的显示顺序
+    pub const ALL: [GameMode; 15] = [
+        GameMode::Endless,
+        GameMode::Sprint(DEFAULT_SPRINT_LINES),
+        GameMode::Ultra(DEFAULT_ULTRA_SECONDS),
+        GameMode::Marathon(DEFAULT_MARATHON_LINES),
+        GameMode::Dig(DEFAULT_DIG_LINES),
+        GameMode::Survival,
+        GameMode::Master,
+        GameMode::Classic,
+        GameMode::Zen,
+        GameMode::Puzzle(0),
+        GameMode::Fading,
+        GameMode::Invisible,
+        GameMode::Big,
+        GameMode::Cascade,
+        GameMode::Versus,
+    ];
+
+    //用配置文件中的参数替换模式的默认参数
+    pub fn with_config(self, config: &ModesConfig) -> Self {
+        match self {
+            GameMode::Endless => GameMode::Endless,
+            GameMode::Sprint(_) => GameMode::Sprint(config.sprint_lines.max(1)),
+            GameMode::Ultra(_) => GameMode::Ultra(config.ultra_seconds.max(1)),
+            GameMode::Marathon(_) => GameMode::Marathon(config.marathon_lines),
+            GameMode::Dig(_) => GameMode::Dig(config.dig_lines.max(1)),
+            GameMode::Survival => GameMode::Survival,
+            GameMode::Master => GameMode::Master,
+            GameMode::Classic => GameMode::Classic,
+            GameMode::Zen => GameMode::Zen,
+            GameMode::Puzzle(index) => GameMode::Puzzle(index),
+            GameMode::Fading => GameMode::Fading,
+            GameMode::Invisible => GameMode::Invisible,
+            GameMode::Big => GameMode::Big,
+            GameMode::Cascade => GameMode::Cascade,
+            GameMode::Versus => GameMode::Versus,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GameMode::Endless => "Endless",
+            GameMode::Sprint(_) => "Sprint",
+            GameMode::Ultra(_) => "Ultra",
+            GameMode::Marathon(_) => "Marathon",
+            GameMode::Dig(_) => "Dig",
+            GameMode::Survival => "Survival",
+            GameMode::Master => "Master",
+            GameMode::Classic => "Classic",
+            GameMode::Zen => "Zen",
+            GameMode::Puzzle(_) => "Puzzle",
+            GameMode::Fading => "Fading",
+            GameMode::Invisible => "Invisible",
+            GameMode::Big => "Big",
+            GameMode::Cascade => "Cascade",
+            GameMode::Versus => "Versus",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            GameMode::Endless => "Play until the stack reaches the top",
+            GameMode::Sprint(_) => "Clear the line target as fast as possible",
+            GameMode::Ultra(_) => "Score as much as possible before time runs out",
+            GameMode::Marathon(_) => "Level up every 10 lines and reach the line goal",
+            GameMode::Dig(_) => "Dig through the garbage rows as fast as possible",
+            GameMode::Survival => "Hold out against rising garbage",
+            GameMode::Master => "20G gravity all the way to level 999, graded from 9 to GM",
+            GameMode::Classic => "NES rules: one preview, no kicks, classic gravity and scoring",
+            GameMode::Zen => "No game over and constant gravity, stats carry over between sessions",
+            GameMode::Puzzle(_) => "Reach the goal on a set board with a fixed queue, progress is saved",
+            GameMode::Fading => "Locked pieces fade out after a few seconds, line clears reveal the stack",
+            GameMode::Invisible => "Locked pieces vanish at once, line clears reveal the stack",
+            GameMode::Big => "Every block is 2x2, the pieces move on a 5x10 grid",
+            GameMode::Cascade => "Loose blocks fall after a clear; each clear they cause adds to the chain",
+            GameMode::Versus => "Two players side by side, line clears send garbage to the opponent",
+        }
+    }
+
+    //HUD中显示的当前目标或进度
+    pub fn objective(self, stats: &GameStats) -> String {
+        match self {
+            GameMode::Endless | GameMode::Survival | GameMode::Classic | GameMode::Fading | GameMode::Invisible | GameMode::Big => "Survive".to_string(),
+            GameMode::Sprint(lines) => format!("{} left", lines.saturating_sub(stats.lines)),
+            GameMode::Ultra(seconds) => format_time((seconds as f64 - stats.elapsed).max(0.0)),
+            GameMode::Marathon(0) => format!("Level {}", stats.level),
+            GameMode::Marathon(lines) => format!("{}/{}", stats.lines, lines),
+            GameMode::Dig(lines) => format!("{} left", lines.saturating_sub(stats.garbage_lines)),
+            GameMode::Master => format!("Grade {}", master_grade(stats)),
+            GameMode::Zen => "Relax".to_string(),
+            GameMode::Puzzle(index) => format!("Puzzle {}", index + 1),
+            GameMode::Cascade => format!("Best chain {}", stats.max_chain),
+            GameMode::Versus => "Outlast rival".to_string(),
+        }
+    }
+
+    //是否已经达成模式目标
+    pub fn goal_reached(self, stats: &GameStats) -> bool {
+        match self {
+            //谜题的目标在谜题包中，由puzzle::check_puzzle检查
+            GameMode::Endless | GameMode::Survival | GameMode::Classic | GameMode::Zen | GameMode::Puzzle(_) => false,
+            //对战在一方堆到顶时结束
+            GameMode::Versus => false,
+            GameMode::Fading | GameMode::Invisible | GameMode::Big | GameMode::Cascade => false,
+            GameMode::Sprint(lines) => stats.lines >= lines,
+            GameMode::Ultra(seconds) => stats.elapsed >= seconds as f64,
+            GameMode::Marathon(0) => false,
+            GameMode::Marathon(lines) => stats.lines >= lines,
+            GameMode::Dig(lines) => stats.garbage_lines >= lines,
+            GameMode::Master => stats.level >= MASTER_MAX_LEVEL,
+        }
+    }
+
+    //是否只有达成目标时才记录个人最佳
+    pub fn requires_completion(self) -> bool {
+        match self {
+            GameMode::Endless | GameMode::Marathon(_) | GameMode::Survival | GameMode::Master | GameMode::Classic => false,
+            GameMode::Fading | GameMode::Invisible | GameMode::Big | GameMode::Cascade => false,
+            GameMode::Sprint(_) | GameMode::Ultra(_) | GameMode::Dig(_) | GameMode::Zen | GameMode::Puzzle(_) => true,
+            //对战没有可达成的目标，结果取决于对手，因此从不记录
+            GameMode::Versus => true,
+        }
+    }
+
+    //个人最佳记录和回放文件中使用的名字，不同目标行数分开记录
+    pub fn record_key(self) -> String {
+        match self {
+            GameMode::Endless => "endless".to_string(),
+            GameMode::Sprint(lines) => format!("sprint{}", lines),
+            GameMode::Ultra(seconds) => format!("ultra{}", seconds),
+            GameMode::Marathon(0) => "marathon_endless".to_string(),
+            GameMode::Marathon(lines) => format!("marathon{}", lines),
+            GameMode::Dig(lines) => format!("dig{}", lines),
+            GameMode::Survival => "survival".to_string(),
+            GameMode::Master => "master".to_string(),
+            GameMode::Classic => "classic".to_string(),
+            GameMode::Zen => "zen".to_string(),
+            GameMode::Puzzle(index) => format!("puzzle{}", index + 1),
+            GameMode::Fading => "fading".to_string(),
+            GameMode::Invisible => "invisible".to_string(),
+            GameMode::Big => "big".to_string(),
+            GameMode::Cascade => "cascade".to_string(),
+            GameMode::Versus => "versus".to_string(),
+        }
+    }
+
+    //等级上限，目标150行时为15级，200行时为20级
+    pub fn max_level(self) -> u32 {
+        match self {
+            GameMode::Marathon(0) => MARATHON_ENDLESS_MAX_LEVEL,
+            GameMode::Marathon(lines) => (lines / 10).max(1),
+            GameMode::Master => MASTER_MAX_LEVEL,
+            _ => u32::MAX,
+        }
+    }
+
+    //模式使用的整套规则
+    pub fn ruleset(self) -> Ruleset {
+        match self {
+            GameMode::Endless => Ruleset::original(),
+            GameMode::Marathon(_) => Ruleset::guideline(),
+            GameMode::Master => Ruleset::tgm(),
+            GameMode::Classic => Ruleset::nes(),
+            _ => Ruleset::modern(),
+        }
+    }
+
+    //是否忽略模式选择界面中选择的规则，禅模式的下落速度要保持不变
+    pub fn fixed_rules(self) -> bool {
+        self == GameMode::Zen
+    }
+
+    //每个方块格子在棋盘上占的边长，大方块模式中为2
+    pub fn cell_scale(self) -> i32 {
+        match self {
+            GameMode::Big => 2,
+            _ => 1,
+        }
+    }
+
+    //消行后是否让相连的方块各自下落
+    pub fn cascades(self) -> bool {
+        self == GameMode::Cascade
+    }
+
+    //落定的方块开始淡出前的秒数，为0时立即隐藏，None表示一直显示
+    pub fn fade_delay(self) -> Option<f64> {
+        match self {
+            GameMode::Fading => Some(FADING_DELAY),
+            GameMode::Invisible => Some(0.0),
+            _ => None,
+        }
+    }
+
+    //方块堆到顶时是否结束本局，禅模式改为清除最上面几行
+    pub fn tops_out(self) -> bool {
+        self != GameMode::Zen
+    }
+
+    //判断成绩a是否比b更好
+    pub fn is_better(self, a: &Record, b: &Record) -> bool {
+        match self {
+            GameMode::Endless | GameMode::Ultra(_) | GameMode::Marathon(_) | GameMode::Classic | GameMode::Zen => a.score > b.score,
+            GameMode::Fading | GameMode::Invisible | GameMode::Big | GameMode::Cascade | GameMode::Versus => a.score > b.score,
+            GameMode::Sprint(_) | GameMode::Dig(_) | GameMode::Puzzle(_) => a.time < b.time,
+            GameMode::Survival => a.time > b.time,
+            GameMode::Master => a.score > b.score || (a.score == b.score && a.time < b.time),
+        }
+    }
+}
+
+//大师模式的段位：分数决定9到S9，全部检查点都通过并到达999级时为GM
+pub fn master_grade(stats: &GameStats) -> &'static str {
+    if stats.level >= MASTER_MAX_LEVEL && stats.gm_eligible && stats.score >= GM_CHECKPOINTS[2].2 {
+        return "GM";
+    }
+    MASTER_GRADES.iter().rev().find(|(_, score)| stats.score >= *score).map(|(grade, _)| *grade).unwrap_or("9")
+}
+
+//每局开始时根据模式设置本局的规则
+pub fn setup_mode(
+    mode: Res<GameMode>,
+    mut game_stats: ResMut<GameStats>,
+    rulesets: Res<Rulesets>,
+    mut rules: ResMut<Ruleset>,
+    mut state: ResMut<GameState>,
+) {
+    state.cell_scale = mode.cell_scale();
+    *rules = rulesets.for_mode(*mode);
+    game_stats.max_level = mode.max_level();
+    game_stats.scoring = rules.scoring;
+    game_stats.level = rules.scoring.start_level();
+}
+
+//达成目标时结束本局，限时模式需要每一步都检查
+pub fn check_goal(
+    mode: Res<GameMode>,
+    mut game_stats: ResMut<GameStats>,
+    buffer: Res<InputBuffer>,
+    time: Res<Time<Fixed>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if mode.goal_reached(&game_stats) {
+        //限时模式正好在时间到时结束，其他模式的用时精确到完成目标的那次操作
+        if !matches!(*mode, GameMode::Ultra(_)) {
+            game_stats.finish_early(buffer.press_lag(time.elapsed()));
+        }
+        next_state.set(AppState::FINISHED);
+    }
+}
+
+//计时是否已经开始：可以在第一个方块出现时开始，也可以等到第一次操作
+pub fn timer_should_start(config: &ConfigData, has_input: bool) -> bool {
+    !config.modes_config.timer_on_first_input || has_input
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(level: u32, score: u32, gm_eligible: bool) -> GameStats {
+        GameStats { level, score, gm_eligible, ..default() }
+    }
+
+    #[test]
+    fn master_grades_follow_the_score_table() {
+        for (grade, score) in MASTER_GRADES {
+            assert_eq!(master_grade(&finished(500, score, true)), grade);
+        }
+        assert_eq!(master_grade(&finished(500, 399, true)), "9");
+        assert_eq!(master_grade(&finished(500, 1_000_000, true)), "S9");
+    }
+
+    #[test]
+    fn gm_needs_level_999_eligibility_and_the_final_score() {
+        let (level, _, score) = GM_CHECKPOINTS[2];
+        assert_eq!(master_grade(&finished(level, score, true)), "GM");
+        assert_eq!(master_grade(&finished(level, score - 1, true)), "S9");
+        assert_eq!(master_grade(&finished(level - 1, score, true)), "S9");
+        assert_eq!(master_grade(&finished(level, score, false)), "S9");
+    }
+}
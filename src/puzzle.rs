@@ -0,0 +1,282 @@
+use std::fs;
+
+use bevy::prelude::*;
+use ini::ini;
+use serde::Deserialize;
+
+use crate::game_logic::{AppState, TileBoard, Tiles};
+use crate::garbage::{Garbage, GarbageTile};
+use crate::input::InputBuffer;
+use crate::modes::GameMode;
+use crate::rules::Randomizer;
+use crate::scene::{GameRng, GameState};
+use crate::stats::GameStats;
+
+//谜题包文件路径
+pub const PUZZLE_PACK_PATH: &str = "assets/puzzles/basics.ron";
+//谜题进度文件路径
+pub const PUZZLE_PROGRESS_PATH: &str = "puzzles.ini";
+//谜题文件中方块的写法，顺序与方块序号相同
+const PIECE_NAMES: &str = "IJLOSTZ";
+
+//谜题的目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum PuzzleGoal {
+    //消除棋盘上所有的方块
+    PerfectClear,
+    //用T旋同时消除指定的行数
+    TSpin(u32),
+    //一共消除指定的行数
+    Lines(u32),
+}
+
+impl PuzzleGoal {
+    pub fn label(self) -> String {
+        match self {
+            PuzzleGoal::PerfectClear => "Perfect clear".to_string(),
+            PuzzleGoal::TSpin(1) => "T-spin single".to_string(),
+            PuzzleGoal::TSpin(2) => "T-spin double".to_string(),
+            PuzzleGoal::TSpin(3) => "T-spin triple".to_string(),
+            PuzzleGoal::TSpin(lines) => format!("T-spin {} lines", lines),
+            PuzzleGoal::Lines(lines) => format!("Clear {} lines", lines),
+        }
+    }
+
+    pub fn reached(self, stats: &GameStats) -> bool {
+        match self {
+            PuzzleGoal::PerfectClear => stats.perfect_clears > 0,
+            PuzzleGoal::TSpin(lines) => stats.tspins.get(lines as usize).is_some_and(|count| *count > 0),
+            PuzzleGoal::Lines(lines) => stats.lines >= lines,
+        }
+    }
+}
+
+//一个谜题：初始棋盘、固定的方块序列和目标
+#[derive(Debug, Clone, Deserialize)]
+pub struct Puzzle {
+    pub name: String,
+    //棋盘从上到下的每一行，与底部对齐，'.'为空，IJLOSTZ为对应颜色的方块，其他字符为垃圾方块
+    pub board: Vec<String>,
+    //方块序列，例如"TIO"
+    pub queue: String,
+    pub goal: PuzzleGoal,
+}
+
+impl Puzzle {
+    pub fn pieces(&self) -> Vec<usize> {
+        self.queue.chars().filter_map(|c| PIECE_NAMES.find(c.to_ascii_uppercase())).collect()
+    }
+}
+
+//谜题包和已经完成的进度
+#[derive(Resource, Debug, Clone, Deserialize)]
+pub struct PuzzlePack {
+    pub name: String,
+    pub puzzles: Vec<Puzzle>,
+    //已经完成的谜题数，按顺序解锁
+    #[serde(skip)]
+    pub solved: u32,
+    //下一局要玩的谜题
+    #[serde(skip)]
+    pub current: u32,
+}
+
+impl PuzzlePack {
+    pub fn get(&self, index: u32) -> Option<&Puzzle> {
+        self.puzzles.get(index as usize)
+    }
+
+    //完成当前的谜题，进入下一个，全部完成后回到第一个
+    pub fn advance(&mut self) {
+        self.solved = self.solved.max(self.current + 1);
+        self.current = (self.current + 1) % self.puzzles.len().max(1) as u32;
+    }
+
+    pub fn save_progress(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, format!("[{}]\nsolved = {}\n", self.name, self.solved))
+    }
+}
+
+//读取谜题包和进度，从第一个未完成的谜题开始
+pub fn load_puzzle_pack(path: &str, progress_path: &str) -> PuzzlePack {
+    let parsed = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| ron::from_str::<PuzzlePack>(&content).map_err(|e| e.to_string()));
+    let mut pack = match parsed {
+        Ok(pack) => pack,
+        Err(e) => {
+            warn!("invalid puzzle pack {}: {}", path, e);
+            PuzzlePack { name: "empty".to_string(), puzzles: vec![], solved: 0, current: 0 }
+        },
+    };
+    if let Ok(map) = ini!(safe progress_path) {
+        pack.solved = map.get(&pack.name)
+            .and_then(|values| values.get("solved").cloned().flatten())
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+    }
+    pack.current = if (pack.solved as usize) < pack.puzzles.len() { pack.solved } else { 0 };
+    pack
+}
+
+//谜题模式开始时选择当前的谜题，并把方块序列换成谜题的序列
+pub fn select_puzzle(
+    mut mode: ResMut<GameMode>,
+    pack: Res<PuzzlePack>,
+    mut state: ResMut<GameState>,
+    mut rng: ResMut<GameRng>,
+) {
+    let GameMode::Puzzle(_) = *mode else {
+        return;
+    };
+    *mode = GameMode::Puzzle(pack.current);
+    let Some(puzzle) = pack.get(pack.current) else {
+        return;
+    };
+    rng.queue = puzzle.pieces().into();
+    rng.queue_only = true;
+    //前两个方块放入预览区
+    let previous = state.next_tetromino.1;
+    state.next_tetromino = rng.next_piece(Randomizer::Uniform, previous);
+    let previous = state.next_tetromino.1;
+    state.next_tetromino2 = rng.next_piece(Randomizer::Uniform, previous);
+}
+
+//按谜题布置初始棋盘
+pub fn fill_puzzle_board(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    pack: Res<PuzzlePack>,
+    mut tile_board: ResMut<TileBoard>,
+    tiles: Res<Tiles>,
+    garbage_tile: Res<GarbageTile>,
+) {
+    let GameMode::Puzzle(index) = *mode else {
+        return;
+    };
+    let Some(puzzle) = pack.get(index) else {
+        return;
+    };
+    let rows = puzzle.board.len().min(tile_board.height as usize);
+    for (i, row) in puzzle.board.iter().rev().take(rows).enumerate() {
+        for (x, c) in row.chars().take(tile_board.width as usize).enumerate() {
+            if c == '.' || c == ' ' {
+                continue;
+            }
+            let entity = commands.spawn_empty().id();
+            let transform = tile_board.set(x as u32, i as u32, entity);
+            match PIECE_NAMES.find(c.to_ascii_uppercase()) {
+                Some(piece) => commands.entity(entity).insert((tiles.0[piece].clone(), transform)),
+                None => commands.entity(entity).insert((garbage_tile.0.clone(), transform, Garbage)),
+            };
+        }
+    }
+}
+
+//每个方块落定后检查：达成目标时进入下一个谜题，方块用完仍未达成则失败
+pub fn check_puzzle(
+    mode: Res<GameMode>,
+    mut pack: ResMut<PuzzlePack>,
+    mut game_stats: ResMut<GameStats>,
+    buffer: Res<InputBuffer>,
+    time: Res<Time<Fixed>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let GameMode::Puzzle(index) = *mode else {
+        return;
+    };
+    let Some(puzzle) = pack.get(index) else {
+        return;
+    };
+    if puzzle.goal.reached(&game_stats) {
+        game_stats.finish_early(buffer.press_lag(time.elapsed()));
+        pack.advance();
+        if let Err(err) = pack.save_progress(PUZZLE_PROGRESS_PATH) {
+            error!("failed to save {}: {}", PUZZLE_PROGRESS_PATH, err);
+        }
+        next_state.set(AppState::FINISHED);
+    } else if game_stats.pieces as usize >= puzzle.pieces().len() {
+        next_state.set(AppState::DEAD);
+    }
+}
+
+//HUD中显示的谜题名、目标和剩余方块数
+pub fn puzzle_objective(pack: &PuzzlePack, index: u32, stats: &GameStats) -> String {
+    match pack.get(index) {
+        Some(puzzle) => format!(
+            "{}: {} ({} left)",
+            puzzle.name,
+            puzzle.goal.label(),
+            puzzle.pieces().len().saturating_sub(stats.pieces as usize)
+        ),
+        None => "No puzzle".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzle(name: &str, queue: &str) -> Puzzle {
+        Puzzle { name: name.to_string(), board: vec![], queue: queue.to_string(), goal: PuzzleGoal::Lines(1) }
+    }
+
+    fn pack(count: u32) -> PuzzlePack {
+        PuzzlePack {
+            name: "test".to_string(),
+            puzzles: (0..count).map(|i| puzzle(&i.to_string(), "T")).collect(),
+            solved: 0,
+            current: 0,
+        }
+    }
+
+    #[test]
+    fn pieces_read_the_queue_and_skip_unknown_letters() {
+        assert_eq!(puzzle("a", "TIo").pieces(), [5, 0, 3]);
+        assert_eq!(puzzle("b", "J-L x Z").pieces(), [1, 2, 6]);
+        assert!(puzzle("c", "").pieces().is_empty());
+    }
+
+    #[test]
+    fn advance_unlocks_the_next_puzzle_and_wraps() {
+        let mut pack = pack(3);
+        pack.advance();
+        assert_eq!((pack.solved, pack.current), (1, 1));
+        //重玩已经完成的谜题不会减少进度
+        pack.current = 0;
+        pack.advance();
+        assert_eq!((pack.solved, pack.current), (1, 1));
+        pack.current = 2;
+        pack.advance();
+        assert_eq!((pack.solved, pack.current), (3, 0));
+    }
+
+    #[test]
+    fn saved_progress_is_loaded_back() {
+        let dir = std::env::temp_dir().join(format!("tetris-puzzles-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pack_path = dir.join("pack.ron");
+        let progress_path = dir.join("progress.ini");
+        let (pack_path, progress_path) = (pack_path.to_str().unwrap(), progress_path.to_str().unwrap());
+        fs::write(pack_path, r#"(
+            name: "test",
+            puzzles: [
+                (name: "0", board: [], queue: "T", goal: Lines(1)),
+                (name: "1", board: [], queue: "T", goal: Lines(1)),
+                (name: "2", board: [], queue: "T", goal: Lines(1)),
+            ],
+        )"#).unwrap();
+        let mut saved = pack(3);
+        saved.advance();
+        saved.advance();
+        saved.save_progress(progress_path).unwrap();
+        let loaded = load_puzzle_pack(pack_path, progress_path);
+        assert_eq!((loaded.solved, loaded.current), (2, 2));
+        //全部完成后从第一个开始
+        saved.advance();
+        saved.save_progress(progress_path).unwrap();
+        let loaded = load_puzzle_pack(pack_path, progress_path);
+        assert_eq!((loaded.solved, loaded.current), (3, 0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::garbage::GarbageState;
+use crate::input::{Action, Actions};
+use crate::modes::GameMode;
+use crate::rules::Ruleset;
+use crate::scene;
+
+//回放文件保存的目录
+pub const REPLAY_DIR: &str = "replays";
+
+//本局的回放：模式、规则、方块和垃圾行的随机种子以及每一帧的操作
+#[derive(Resource, Debug, Default)]
+pub struct Replay {
+    pub mode: GameMode,
+    //本局使用的完整规则，规则文件修改后回放仍然可以还原
+    pub rules: Ruleset,
+    pub seed: u64,
+    //垃圾行的随机种子
+    pub garbage_seed: u64,
+    pub actions: Vec<(u64, Action)>,
+}
+
+impl Replay {
+    //保存到回放目录，返回文件路径
+    pub fn save(&self, dir: &str) -> std::io::Result<String> {
+        fs::create_dir_all(dir)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = format!("{}/{}-{}.replay", dir, self.mode.record_key(), timestamp);
+        //规则以单行RON保存
+        let rules = ron::to_string(&self.rules).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let mut content = format!(
+            "mode {}\nrules {}\nseed {}\ngarbage_seed {}\n",
+            self.mode.record_key(), rules, self.seed, self.garbage_seed
+        );
+        for (frame, action) in self.actions.iter() {
+            content.push_str(&format!("{} {}\n", frame, action.name()));
+        }
+        fs::write(&path, content)?;
+        Ok(path)
+    }
+}
+
+//每局开始时重新录制
+pub fn start_recording(
+    mut replay: ResMut<Replay>,
+    mode: Res<GameMode>,
+    rules: Res<Ruleset>,
+    rng: Res<scene::GameRng>,
+    garbage: Res<GarbageState>,
+) {
+    *replay = Replay {
+        mode: *mode,
+        rules: rules.clone(),
+        seed: rng.seed,
+        garbage_seed: garbage.seed,
+        actions: vec![],
+    };
+}
+
+//记录本步的操作和对应的游戏帧
+pub fn record_actions(
+    mut replay: ResMut<Replay>,
+    actions: Res<Actions>,
+    state: Res<scene::GameState>,
+) {
+    for action in actions.0.iter() {
+        replay.actions.push((state.frame_counter, *action));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use bevy::ecs::schedule::ScheduleLabel;
+    use rand::SeedableRng;
+
+    use crate::config::ConfigData;
+    use crate::game_logic::{self, AppState, TileBoard};
+    use crate::input::InputBuffer;
+    use crate::stats::GameStats;
+
+    #[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+    struct Step;
+
+    fn board_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(scene::init_game_state(&mut rand::rngs::StdRng::seed_from_u64(7)));
+        world.insert_resource(TileBoard::new(10, 20, 1.0, 1.0));
+        world.insert_resource(Ruleset::modern());
+        world.insert_resource(ConfigData::new());
+        world.insert_resource(GameStats::default());
+        world.insert_resource(InputBuffer::default());
+        world.insert_resource(Actions::default());
+        world.insert_resource(Replay::default());
+        world.insert_resource(Time::<Fixed>::default());
+        world.insert_resource(NextState::<AppState>::default());
+        world
+    }
+
+    //运行一个固定步长，返回本步之后方块的位置和方向
+    fn step(world: &mut World, actions: Vec<Action>) -> (IVec2, usize) {
+        world.resource_mut::<Time<Fixed>>().advance_by(Duration::from_secs_f64(1.0 / 60.0));
+        world.resource_mut::<Actions>().0 = actions;
+        world.run_schedule(Step);
+        let state = world.resource::<scene::GameState>();
+        (state.current_position, state.current_tetromino.rotate)
+    }
+
+    #[test]
+    fn recorded_frames_replay_to_the_same_board() {
+        //与main.rs中的顺序相同：先记录操作，再运行棋盘
+        let mut recording = board_world();
+        let mut schedule = Schedule::new(Step);
+        schedule.add_systems((
+            record_actions,
+            game_logic::handle_actions,
+            game_logic::update_timer,
+            game_logic::step_down,
+        ).chain());
+        recording.add_schedule(schedule);
+        let script = [(3, Action::Left), (5, Action::RotateRight), (8, Action::Left), (12, Action::Right)];
+        let recorded: Vec<(IVec2, usize)> = (0..20)
+            .map(|i| {
+                let actions = script.iter().filter(|(frame, _)| *frame == i).map(|(_, action)| *action).collect();
+                step(&mut recording, actions)
+            })
+            .collect();
+        let replay = recording.remove_resource::<Replay>().unwrap();
+        assert_eq!(replay.actions.len(), script.len());
+
+        //回放时在帧号相同的一步执行记录的操作
+        let mut playback = board_world();
+        let mut schedule = Schedule::new(Step);
+        schedule.add_systems((
+            game_logic::handle_actions,
+            game_logic::update_timer,
+            game_logic::step_down,
+        ).chain());
+        playback.add_schedule(schedule);
+        let replayed: Vec<(IVec2, usize)> = (0..20)
+            .map(|_| {
+                let frame = playback.resource::<scene::GameState>().frame_counter;
+                let actions = replay.actions.iter().filter(|(f, _)| *f == frame).map(|(_, action)| *action).collect();
+                step(&mut playback, actions)
+            })
+            .collect();
+        assert_eq!(recorded, replayed);
+    }
+}
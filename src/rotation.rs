@@ -0,0 +1,237 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::tetromino::{Tetromino, TetrominoType};
+
+//旋转系统，决定每个方块的四个旋转状态和旋转受阻时的踢墙规则
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum RotationSystem {
+    //原有的旋转状态，不踢墙
+    #[default]
+    NoKick,
+    //标准旋转系统(SRS)
+    Srs,
+    //街机旋转系统(ARS)，状态贴底，只尝试左右各踢一格
+    Ars,
+    //任天堂旋转系统(NRS)，I、S、Z只有两个状态且竖起时偏右，不踢墙
+    Nrs,
+}
+
+//SRS中J、L、S、T、Z的踢墙偏移，顺时针依次为0->R、R->2、2->L、L->0
+const SRS_JLSTZ_CW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+//逆时针依次为0->L、R->0、2->R、L->2
+const SRS_JLSTZ_CCW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+];
+const SRS_I_CW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+];
+const SRS_I_CCW: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+    [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+];
+
+fn cells(list: [(i32, i32); 4]) -> [IVec2; 4] {
+    list.map(|(x, y)| IVec2::new(x, y))
+}
+
+//由初始状态顺时针旋转得到四个状态，f为顺时针旋转一次的坐标变换
+fn rotations(first: [IVec2; 4], f: impl Fn(IVec2) -> IVec2) -> [[IVec2; 4]; 4] {
+    let mut result = [first; 4];
+    for i in 1..4 {
+        result[i] = result[i - 1].map(&f);
+    }
+    result
+}
+
+impl RotationSystem {
+    pub fn label(self) -> &'static str {
+        match self {
+            RotationSystem::NoKick => "No kick",
+            RotationSystem::Srs => "SRS",
+            RotationSystem::Ars => "ARS",
+            RotationSystem::Nrs => "NRS",
+        }
+    }
+
+    //按旋转系统的状态生成方块
+    pub fn new_piece(self, tetromino_type: TetrominoType, index: usize) -> Tetromino {
+        let mut piece = Tetromino::new(tetromino_type, index);
+        if self != RotationSystem::NoKick {
+            piece.positions = self.positions(tetromino_type);
+        }
+        piece
+    }
+
+    //四个旋转状态，坐标相对于方块的位置，y轴向上
+    pub fn positions(self, tetromino_type: TetrominoType) -> [[IVec2; 4]; 4] {
+        match self {
+            RotationSystem::NoKick => Tetromino::new(tetromino_type, 0).positions,
+            RotationSystem::Srs => srs_positions(tetromino_type),
+            RotationSystem::Ars => ars_positions(tetromino_type),
+            RotationSystem::Nrs => nrs_positions(tetromino_type),
+        }
+    }
+
+    //尝试旋转到to状态，blocked判断相对于方块位置的格子是否被占用(不包括方块自身)
+    //可以旋转时返回方块需要的位移
+    pub fn rotate(self, piece: &Tetromino, to: usize, blocked: impl Fn(IVec2) -> bool) -> Option<IVec2> {
+        let target = piece.get_position2(to);
+        let fits = |offset: IVec2| target.iter().all(|p| !blocked(*p + offset));
+        match self {
+            RotationSystem::NoKick | RotationSystem::Nrs => fits(IVec2::ZERO).then_some(IVec2::ZERO),
+            RotationSystem::Srs => {
+                let clockwise = to == (piece.rotate + 1) % 4;
+                let table = match (piece.tetromino_type, clockwise) {
+                    (TetrominoType::O, _) => return fits(IVec2::ZERO).then_some(IVec2::ZERO),
+                    (TetrominoType::I, true) => &SRS_I_CW,
+                    (TetrominoType::I, false) => &SRS_I_CCW,
+                    (_, true) => &SRS_JLSTZ_CW,
+                    (_, false) => &SRS_JLSTZ_CCW,
+                };
+                table[piece.rotate].iter().map(|(x, y)| IVec2::new(*x, *y)).find(|offset| fits(*offset))
+            },
+            RotationSystem::Ars => {
+                if fits(IVec2::ZERO) {
+                    return Some(IVec2::ZERO);
+                }
+                //I不踢墙
+                if piece.tetromino_type == TetrominoType::I {
+                    return None;
+                }
+                //T、J、L：按从上到下、从左到右的顺序，第一个挡住的格子在中间一列时不踢墙
+                if matches!(piece.tetromino_type, TetrominoType::T | TetrominoType::J | TetrominoType::L) {
+                    let first_blocked = (-1..=1).rev()
+                        .flat_map(|y| (-1..=1).map(move |x| IVec2::new(x, y)))
+                        .find(|p| target.contains(p) && blocked(*p));
+                    if first_blocked.is_some_and(|p| p.x == 0) {
+                        return None;
+                    }
+                }
+                [IVec2::new(1, 0), IVec2::new(-1, 0)].into_iter().find(|offset| fits(*offset))
+            },
+        }
+    }
+}
+
+//SRS的状态：3x3的方块绕中心格旋转，I和O绕4x4和2x2区域的中心旋转
+fn srs_positions(tetromino_type: TetrominoType) -> [[IVec2; 4]; 4] {
+    let three = |p: IVec2| IVec2::new(p.y, -p.x);
+    match tetromino_type {
+        TetrominoType::I => rotations(cells([(-1, 0), (0, 0), (1, 0), (2, 0)]), |p| IVec2::new(p.y + 1, -p.x)),
+        TetrominoType::O => rotations(cells([(0, 0), (1, 0), (0, 1), (1, 1)]), |p| IVec2::new(p.y, 1 - p.x)),
+        TetrominoType::J => rotations(cells([(-1, 1), (-1, 0), (0, 0), (1, 0)]), three),
+        TetrominoType::L => rotations(cells([(1, 1), (-1, 0), (0, 0), (1, 0)]), three),
+        TetrominoType::S => rotations(cells([(0, 1), (1, 1), (-1, 0), (0, 0)]), three),
+        TetrominoType::T => rotations(cells([(0, 1), (-1, 0), (0, 0), (1, 0)]), three),
+        TetrominoType::Z => rotations(cells([(-1, 1), (0, 1), (0, 0), (1, 0)]), three),
+    }
+}
+
+//ARS的状态：在3x3区域内贴底，平的一面朝上出现
+fn ars_positions(tetromino_type: TetrominoType) -> [[IVec2; 4]; 4] {
+    match tetromino_type {
+        TetrominoType::I => {
+            let flat = cells([(-1, 0), (0, 0), (1, 0), (2, 0)]);
+            let upright = cells([(1, 1), (1, 0), (1, -1), (1, -2)]);
+            [flat, upright, flat, upright]
+        },
+        TetrominoType::O => [cells([(0, 0), (1, 0), (0, -1), (1, -1)]); 4],
+        TetrominoType::J => [
+            cells([(-1, 0), (0, 0), (1, 0), (1, -1)]),
+            cells([(0, 1), (0, 0), (0, -1), (-1, -1)]),
+            cells([(-1, 0), (-1, -1), (0, -1), (1, -1)]),
+            cells([(0, 1), (1, 1), (0, 0), (0, -1)]),
+        ],
+        TetrominoType::L => [
+            cells([(-1, 0), (0, 0), (1, 0), (-1, -1)]),
+            cells([(-1, 1), (0, 1), (0, 0), (0, -1)]),
+            cells([(1, 0), (-1, -1), (0, -1), (1, -1)]),
+            cells([(0, 1), (0, 0), (0, -1), (1, -1)]),
+        ],
+        TetrominoType::S => {
+            let flat = cells([(0, 0), (1, 0), (-1, -1), (0, -1)]);
+            let upright = cells([(-1, 1), (-1, 0), (0, 0), (0, -1)]);
+            [flat, upright, flat, upright]
+        },
+        TetrominoType::T => [
+            cells([(-1, 0), (0, 0), (1, 0), (0, -1)]),
+            cells([(0, 1), (0, 0), (0, -1), (-1, 0)]),
+            cells([(-1, -1), (0, -1), (1, -1), (0, 0)]),
+            cells([(0, 1), (0, 0), (0, -1), (1, 0)]),
+        ],
+        TetrominoType::Z => {
+            let flat = cells([(-1, 0), (0, 0), (0, -1), (1, -1)]);
+            let upright = cells([(1, 1), (0, 0), (1, 0), (0, -1)]);
+            [flat, upright, flat, upright]
+        },
+    }
+}
+
+//NRS的状态：T、J、L绕中心格旋转，平的一面朝上出现
+fn nrs_positions(tetromino_type: TetrominoType) -> [[IVec2; 4]; 4] {
+    let two = |flat: [IVec2; 4], upright: [IVec2; 4]| [flat, upright, flat, upright];
+    let three = |p: IVec2| IVec2::new(p.y, -p.x);
+    match tetromino_type {
+        TetrominoType::I => two(cells([(-2, 0), (-1, 0), (0, 0), (1, 0)]), cells([(0, 2), (0, 1), (0, 0), (0, -1)])),
+        TetrominoType::O => [cells([(-1, 0), (0, 0), (-1, -1), (0, -1)]); 4],
+        TetrominoType::J => rotations(cells([(-1, 0), (0, 0), (1, 0), (1, -1)]), three),
+        TetrominoType::L => rotations(cells([(-1, 0), (0, 0), (1, 0), (-1, -1)]), three),
+        TetrominoType::S => two(cells([(0, 0), (1, 0), (-1, -1), (0, -1)]), cells([(0, 1), (0, 0), (1, 0), (1, -1)])),
+        TetrominoType::T => rotations(cells([(-1, 0), (0, 0), (1, 0), (0, -1)]), three),
+        TetrominoType::Z => two(cells([(-1, 0), (0, 0), (0, -1), (1, -1)]), cells([(1, 1), (0, 0), (1, 0), (0, -1)])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srs_t_rotates_clockwise_around_center() {
+        let states = srs_positions(TetrominoType::T);
+        //朝右的T：中间一列加右边一格
+        let mut right = states[1].to_vec();
+        right.sort_by_key(|p| (p.x, p.y));
+        assert_eq!(right, vec![IVec2::new(0, -1), IVec2::new(0, 0), IVec2::new(0, 1), IVec2::new(1, 0)]);
+    }
+
+    #[test]
+    fn srs_kicks_off_the_wall() {
+        let piece = RotationSystem::Srs.new_piece(TetrominoType::T, 5);
+        //左边一列被挡住，0->L需要向右踢一格
+        let kick = RotationSystem::Srs.rotate(&piece, 3, |p| p.x < 0);
+        assert_eq!(kick, Some(IVec2::new(1, 0)));
+    }
+
+    #[test]
+    fn ars_i_never_kicks() {
+        let piece = RotationSystem::Ars.new_piece(TetrominoType::I, 0);
+        let kick = RotationSystem::Ars.rotate(&piece, 1, |p| p.y < -1);
+        assert_eq!(kick, None);
+    }
+
+    #[test]
+    fn ars_center_column_blocks_kick() {
+        let piece = RotationSystem::Ars.new_piece(TetrominoType::T, 5);
+        //只有中间一列顶上被挡住时不踢墙
+        let kick = RotationSystem::Ars.rotate(&piece, 1, |p| p == IVec2::new(0, 1));
+        assert_eq!(kick, None);
+        //左边被挡住时向右踢一格
+        let kick = RotationSystem::Ars.rotate(&piece, 1, |p| p.x < 0);
+        assert_eq!(kick, Some(IVec2::new(1, 0)));
+    }
+}
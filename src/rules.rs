@@ -0,0 +1,407 @@
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::GameConfig;
+use crate::modes::GameMode;
+use crate::rotation::RotationSystem;
+use crate::scene;
+use crate::tetromino::TetrominoType;
+
+//自定义规则文件所在的目录，支持.ron和.toml，内置规则只在代码中定义
+pub const RULES_DIR: &str = "assets/rules";
+//每秒的帧数，重力以每帧下落的行数(G)表示
+pub const FRAMES_PER_SECOND: f64 = 60.0;
+//TGM规则每100级一段，每段的锁定延迟和出块延迟(帧)
+const MASTER_LOCK_FRAMES: [u32; 10] = [30, 30, 30, 30, 30, 28, 26, 22, 18, 15];
+const MASTER_ARE_FRAMES: [u32; 10] = [25, 25, 25, 25, 25, 16, 12, 12, 6, 6];
+//NES每个等级下落一格需要的帧数，29级以后都是1帧
+const NES_FRAMES_PER_ROW: [u32; 29] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6,
+    5, 5, 5, 4, 4, 4, 3, 3, 3, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+//NES的出块延迟(帧)
+const NES_ARE_FRAMES: u32 = 10;
+
+//下落、锁定和出块的时间参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    //每帧下落的行数，20表示方块直接落到底
+    pub gravity: f64,
+    //方块着地后到锁定的时间(秒)
+    pub lock_delay: f64,
+    //方块锁定后到下一个方块出现的时间(秒)
+    pub are: f64,
+}
+
+//重力规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gravity {
+    //固定使用配置文件中的下落间隔
+    Fixed,
+    //标准规则的重力曲线，随等级加速
+    Curve,
+    //NES的每格帧数表，没有锁定延迟
+    Nes,
+    //20G，锁定延迟和出块延迟按段缩短
+    Master,
+}
+
+//计分方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Scoring {
+    //标准规则，从1级开始，每10行升一级
+    #[default]
+    Guideline,
+    //NES规则，从0级开始，每10行升一级，一次消除1到4行得40/100/300/1200乘以(等级+1)
+    Nes,
+    //大师模式，每个方块和每行都增加等级，每段的最后一级需要消行才能通过
+    Master,
+}
+
+impl Scoring {
+    pub fn start_level(self) -> u32 {
+        match self {
+            Scoring::Guideline => 1,
+            Scoring::Nes | Scoring::Master => 0,
+        }
+    }
+}
+
+//方块的随机方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Randomizer {
+    //每种方块概率相同
+    Uniform,
+    //NES：先在8个结果中取一个，结果无效或与上一个方块相同时重新取一次
+    Nes,
+}
+
+impl Randomizer {
+    pub fn next(self, random: &mut impl Rng, previous: usize) -> (TetrominoType, usize) {
+        match self {
+            Randomizer::Uniform => scene::get_rand_tetromino(random),
+            Randomizer::Nes => {
+                let roll = random.gen_range(0..8);
+                if roll == 7 || roll == previous {
+                    scene::get_rand_tetromino(random)
+                } else {
+                    scene::tetromino_from_index(roll)
+                }
+            },
+        }
+    }
+}
+
+//一整套规则：旋转、随机、预览数、自动重复、重力、锁定延迟和计分
+//规则文件中省略的字段使用原有规则的值
+//游戏没有暂存(hold)功能，所以规则中也没有对应的字段
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ruleset {
+    pub name: String,
+    pub rotation: RotationSystem,
+    pub randomizer: Randomizer,
+    //预览的方块数，1或2
+    pub previews: u32,
+    //首次重复延迟和重复间隔(帧)，为None时使用配置文件中的设置
+    pub das: Option<(u32, u32)>,
+    pub gravity: Gravity,
+    //锁定延迟(帧)，为None时使用配置文件中的下落间隔，NES和大师重力有自己的锁定延迟
+    pub lock_delay: Option<u32>,
+    pub scoring: Scoring,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Self::original()
+    }
+}
+
+impl Ruleset {
+    //原有的规则：不踢墙，固定的下落速度
+    pub fn original() -> Self {
+        Self {
+            name: "Original".to_string(),
+            rotation: RotationSystem::NoKick,
+            randomizer: Randomizer::Uniform,
+            previews: 2,
+            das: None,
+            gravity: Gravity::Fixed,
+            lock_delay: None,
+            scoring: Scoring::Guideline,
+        }
+    }
+
+    //SRS旋转，固定的下落速度，用于竞速、限时等模式
+    pub fn modern() -> Self {
+        Self {
+            name: "Modern".to_string(),
+            rotation: RotationSystem::Srs,
+            ..Self::original()
+        }
+    }
+
+    //标准规则：SRS旋转，随等级加速，半秒的锁定延迟
+    pub fn guideline() -> Self {
+        Self {
+            name: "Guideline".to_string(),
+            rotation: RotationSystem::Srs,
+            gravity: Gravity::Curve,
+            lock_delay: Some(30),
+            ..Self::original()
+        }
+    }
+
+    //NES俄罗斯方块：一个预览，右手旋转不踢墙，16/6的自动重复
+    pub fn nes() -> Self {
+        Self {
+            name: "NES".to_string(),
+            rotation: RotationSystem::Nrs,
+            randomizer: Randomizer::Nes,
+            previews: 1,
+            das: Some((16, 6)),
+            gravity: Gravity::Nes,
+            lock_delay: None,
+            scoring: Scoring::Nes,
+        }
+    }
+
+    //TGM：ARS旋转，20G
+    pub fn tgm() -> Self {
+        Self {
+            name: "TGM".to_string(),
+            rotation: RotationSystem::Ars,
+            randomizer: Randomizer::Uniform,
+            previews: 1,
+            das: None,
+            gravity: Gravity::Master,
+            lock_delay: None,
+            scoring: Scoring::Master,
+        }
+    }
+
+    //当前等级的下落、锁定和出块时间
+    pub fn timing(&self, level: u32, config: &GameConfig) -> Timing {
+        let step_gravity = |delay: f64| 1.0 / (delay * FRAMES_PER_SECOND);
+        let lock_delay = self.lock_delay.map(|frames| frames as f64 / FRAMES_PER_SECOND).unwrap_or(config.step_delay);
+        match self.gravity {
+            Gravity::Fixed => Timing {
+                gravity: step_gravity(config.step_delay),
+                lock_delay,
+                are: 0.0,
+            },
+            Gravity::Curve => Timing {
+                gravity: step_gravity(gravity_delay(level)),
+                lock_delay,
+                are: 0.0,
+            },
+            Gravity::Nes => {
+                let frames = NES_FRAMES_PER_ROW.get(level as usize).copied().unwrap_or(1);
+                //没有锁定延迟，着地后的下一次下落时锁定
+                Timing {
+                    gravity: 1.0 / frames as f64,
+                    lock_delay: frames as f64 / FRAMES_PER_SECOND,
+                    are: NES_ARE_FRAMES as f64 / FRAMES_PER_SECOND,
+                }
+            },
+            Gravity::Master => {
+                let section = ((level / 100) as usize).min(MASTER_LOCK_FRAMES.len() - 1);
+                Timing {
+                    gravity: 20.0,
+                    lock_delay: MASTER_LOCK_FRAMES[section] as f64 / FRAMES_PER_SECOND,
+                    are: MASTER_ARE_FRAMES[section] as f64 / FRAMES_PER_SECOND,
+                }
+            },
+        }
+    }
+
+    //首次重复延迟和重复间隔(帧)
+    pub fn das(&self, config: &GameConfig) -> (u32, u32) {
+        self.das.unwrap_or((config.first_repeat_delay, config.repeat_delay))
+    }
+}
+
+//标准规则的重力曲线：(0.8 - (等级 - 1) * 0.007) ^ (等级 - 1)
+pub fn gravity_delay(level: u32) -> f64 {
+    let n = level.max(1) as f64 - 1.0;
+    (0.8 - n * 0.007).powf(n)
+}
+
+//所有可选的规则：内置的规则加上规则目录中的文件，同名的文件覆盖内置规则
+#[derive(Resource, Debug, Clone)]
+pub struct Rulesets {
+    pub presets: Vec<Ruleset>,
+    //模式选择界面中选择的规则，为None时使用模式默认的规则
+    pub selected: Option<usize>,
+}
+
+impl Rulesets {
+    //本局使用的规则
+    pub fn for_mode(&self, mode: GameMode) -> Ruleset {
+        if mode.fixed_rules() {
+            return mode.ruleset();
+        }
+        self.selected
+            .and_then(|i| self.presets.get(i))
+            .cloned()
+            .unwrap_or_else(|| mode.ruleset())
+    }
+
+    //在模式默认的规则和所有可选的规则之间循环切换
+    pub fn cycle(&mut self, step: isize) {
+        let len = self.presets.len() as isize + 1;
+        let current = self.selected.map_or(0, |i| i as isize + 1);
+        let next = (current + step).rem_euclid(len) as usize;
+        self.selected = next.checked_sub(1);
+    }
+}
+
+//读取规则文件，按扩展名选择RON或TOML格式
+pub fn parse_ruleset(path: &Path) -> Result<Ruleset, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ron") => ron::from_str(&content).map_err(|e| e.to_string()),
+        Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+        _ => Err("unknown rules format".to_string()),
+    }
+}
+
+pub fn load_rulesets(dir: &str) -> Rulesets {
+    let mut rulesets = Rulesets {
+        presets: vec![Ruleset::guideline(), Ruleset::nes(), Ruleset::tgm()],
+        selected: None,
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return rulesets;
+    };
+    let mut paths: Vec<_> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        let rules = match parse_ruleset(&path) {
+            Ok(rules) => rules,
+            Err(e) => {
+                warn!("invalid rules file {}: {}", path.display(), e);
+                continue;
+            },
+        };
+        match rulesets.presets.iter_mut().find(|r| r.name == rules.name) {
+            Some(existing) => *existing = rules,
+            None => rulesets.presets.push(rules),
+        }
+    }
+    rulesets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    //按顺序返回给定的值，gen_range(0..8)取最高的3位，值为0时gen_range(0..7)得到0
+    struct Scripted(Vec<u64>);
+
+    impl RngCore for Scripted {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+        fn next_u64(&mut self) -> u64 {
+            self.0.remove(0)
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for byte in dest {
+                *byte = self.next_u64() as u8;
+            }
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    fn roll(value: u64) -> u64 {
+        value << 61
+    }
+
+    #[test]
+    fn nes_randomizer_keeps_a_new_piece() {
+        let mut rng = Scripted(vec![roll(5)]);
+        assert_eq!(Randomizer::Nes.next(&mut rng, 3).1, 5);
+        assert!(rng.0.is_empty());
+    }
+
+    #[test]
+    fn nes_randomizer_rerolls_a_repeat() {
+        let mut rng = Scripted(vec![roll(3), 0]);
+        assert_eq!(Randomizer::Nes.next(&mut rng, 3).1, 0);
+        assert!(rng.0.is_empty());
+    }
+
+    #[test]
+    fn nes_randomizer_rerolls_the_eighth_result() {
+        let mut rng = Scripted(vec![roll(7), 0]);
+        assert_eq!(Randomizer::Nes.next(&mut rng, 3).1, 0);
+        assert!(rng.0.is_empty());
+    }
+
+    #[test]
+    fn nes_timing_follows_the_frame_table() {
+        let config = crate::config::ConfigData::new().game_config;
+        let rules = Ruleset::nes();
+        for (level, frames) in [(0, 48.0), (9, 6.0), (19, 2.0), (29, 1.0)] {
+            let timing = rules.timing(level, &config);
+            assert_eq!(timing.gravity, 1.0 / frames, "level {}", level);
+            assert_eq!(timing.lock_delay, frames / FRAMES_PER_SECOND, "level {}", level);
+            assert_eq!(timing.are, NES_ARE_FRAMES as f64 / FRAMES_PER_SECOND);
+        }
+    }
+
+    //写入临时目录中的规则文件并读取
+    fn parse_file(name: &str, content: &str) -> Result<Ruleset, String> {
+        let dir = std::env::temp_dir().join(format!("tetris-rules-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        let rules = parse_ruleset(&path);
+        fs::remove_file(&path).unwrap();
+        rules
+    }
+
+    #[test]
+    fn ron_rules_round_trip() {
+        for rules in [Ruleset::guideline(), Ruleset::nes(), Ruleset::tgm()] {
+            let content = ron::to_string(&rules).unwrap();
+            assert_eq!(parse_file("round_trip.ron", &content), Ok(rules));
+        }
+    }
+
+    #[test]
+    fn toml_rules_round_trip() {
+        for rules in [Ruleset::guideline(), Ruleset::nes(), Ruleset::tgm()] {
+            let content = toml::to_string(&rules).unwrap();
+            assert_eq!(parse_file("round_trip.toml", &content), Ok(rules));
+        }
+    }
+
+    #[test]
+    fn omitted_fields_fall_back_to_the_original_rules() {
+        let expected = Ruleset {
+            name: "Kicks".to_string(),
+            rotation: RotationSystem::Srs,
+            ..Ruleset::original()
+        };
+        let ron = parse_file("partial.ron", "(name: \"Kicks\", rotation: Srs)");
+        assert_eq!(ron, Ok(expected.clone()));
+        let toml = parse_file("partial.toml", "name = \"Kicks\"\nrotation = \"Srs\"\n");
+        assert_eq!(toml, Ok(expected));
+    }
+
+    #[test]
+    fn unknown_rules_format_is_rejected() {
+        assert!(parse_file("rules.txt", "name = \"Kicks\"").is_err());
+    }
+}
@@ -1,8 +1,10 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
 use bevy::prelude::*;
-use rand::{thread_rng, Rng};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
 
 use crate::config::*;
+use crate::rules::Randomizer;
 use crate::tetromino::*;
 
 
@@ -12,6 +14,22 @@ pub struct FstPreview;
 #[derive(Component, Debug)]
 pub struct SndPreview;
 
+//预览区2的边框，只有一个预览时隐藏
+#[derive(Component, Debug)]
+pub struct SndPreviewFrame;
+
+//预览等实体所属的棋盘，单人游戏中只有0号棋盘
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Player(pub usize);
+
+//对战模式中相邻两个棋盘中心的距离(格)
+const BOARD_SPACING: f32 = 26.0;
+
+//棋盘中心的横坐标，tile为一格的像素大小
+pub fn board_offset(player: usize, tile: f32) -> f32 {
+    player as f32 * BOARD_SPACING * tile
+}
+
 #[derive(Resource, Debug)]
 pub struct GameState {
     // pub alive: bool,
@@ -24,15 +42,74 @@ pub struct GameState {
     pub step_timer: f64,
     pub _move_timer: f64,  // 保留用于其他时间相关功能
     pub hit_bottom_timer: f64,
+    //出块延迟剩余的秒数，为None时有正在下落的方块
+    pub entry_timer: Option<f64>,
+    //最后一次成功的移动是否为旋转，用于判断T旋
+    pub last_move_rotation: bool,
+    //每个方块格子在棋盘上占的边长，大方块模式中为2，方块坐标以格子为单位
+    pub cell_scale: i32,
     // 新增帧控制相关字段
     pub frame_counter: u64,
-    pub key_press_start_frame: Option<u64>,
-    pub last_repeat_frame: u64,
 }
 
-pub fn get_rand_tetromino() -> (TetrominoType, usize) {
-    let mut random = thread_rng();
-    let num = random.gen_range(0..7);
+//方块生成使用的随机数，每局重新设定种子，种子会保存到回放中
+#[derive(Resource, Debug)]
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: StdRng,
+    //固定的方块序列(方块序号)，用完之前不使用随机数
+    pub queue: VecDeque<usize>,
+    //是否只有固定的序列，谜题模式中序列用完后的方块不显示在预览区
+    pub queue_only: bool,
+    //序列用完后又取出的方块数
+    pub past_queue: u32,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            queue: VecDeque::new(),
+            queue_only: false,
+            past_queue: 0,
+        }
+    }
+
+    //下一个方块，优先从固定的序列中取
+    pub fn next_piece(&mut self, randomizer: Randomizer, previous: usize) -> (TetrominoType, usize) {
+        match self.queue.pop_front() {
+            Some(index) => tetromino_from_index(index),
+            None => {
+                if self.queue_only {
+                    self.past_queue += 1;
+                }
+                randomizer.next(&mut self.rng, previous)
+            },
+        }
+    }
+
+    //预览区显示的方块数，只有固定序列时不显示序列之后的方块
+    pub fn shown_previews(&self, previews: u32) -> u32 {
+        if self.queue_only {
+            previews.min(2u32.saturating_sub(self.past_queue))
+        } else {
+            previews
+        }
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(thread_rng().gen())
+    }
+}
+
+pub fn get_rand_tetromino(random: &mut impl Rng) -> (TetrominoType, usize) {
+    tetromino_from_index(random.gen_range(0..7))
+}
+
+pub fn tetromino_from_index(num: usize) -> (TetrominoType, usize) {
     match num {
         0 => (TetrominoType::I, 0),
         1 => (TetrominoType::J, 1),
@@ -57,6 +134,11 @@ pub fn make_tile(asset_server: &Res<AssetServer>, tetromino_type: TetrominoType)
     }
 }
 
+//垃圾行使用灰色的方块，与七种方块的颜色区分
+pub fn make_garbage_tile(asset_server: &Res<AssetServer>) -> Sprite {
+    Sprite::from_image(asset_server.load("Garbage.png"))
+}
+
 pub fn make_sprite(asset_server: &Res<AssetServer>, tetromino_type: TetrominoType) -> Sprite {
     match tetromino_type {
         TetrominoType::I => Sprite::from_image(asset_server.load("I.png")),
@@ -69,22 +151,23 @@ pub fn make_sprite(asset_server: &Res<AssetServer>, tetromino_type: TetrominoTyp
     }
 } 
 
-pub fn init_game_state() -> GameState {
-    let t = get_rand_tetromino();
+pub fn init_game_state(random: &mut impl Rng) -> GameState {
+    let t = get_rand_tetromino(random);
     GameState {
         // alive: true,
         // paused: false,
         current_tetromino: Tetromino::new(t.0, t.1),
-        next_tetromino: get_rand_tetromino(),
-        next_tetromino2: get_rand_tetromino(),
+        next_tetromino: get_rand_tetromino(random),
+        next_tetromino2: get_rand_tetromino(random),
         current_position: IVec2::new(4, 18),
         tetromino_entities: HashSet::new(),
         hit_bottom_timer: 0.0,
+        entry_timer: None,
+        last_move_rotation: false,
+        cell_scale: 1,
         step_timer: 0.0,
         _move_timer: 0.0,
         frame_counter: 0,
-        key_press_start_frame: None,
-        last_repeat_frame: 0,
     }
 }
 
@@ -92,16 +175,17 @@ pub fn init_game_state() -> GameState {
 //     Camera2dBundle::default()
 // }
 
-pub fn calculate_preview_transform(config: &Res<ConfigData>, fst: bool) -> Transform {
-    let x = config.game_config.tile_size * config.game_config.scale_factor * 8.0;
+pub fn calculate_preview_transform(config: &Res<ConfigData>, player: usize, fst: bool) -> Transform {
+    let tile = config.game_config.tile_size * config.game_config.scale_factor;
+    let x = tile * 8.0 + board_offset(player, tile);
     let y = config.game_config.tile_size * config.game_config.scale_factor * (if fst { 8.0 } else { 3.0 });
     Transform::from_scale(Vec3::new(config.game_config.scale_factor, config.game_config.scale_factor, 1.0))
         .with_translation(Vec3::new(x, y, 0.0))
 }
 
-pub fn preview_board(asset_server: &Res<AssetServer>, config: &Res<ConfigData>, fst: bool) -> impl Bundle {
+pub fn preview_board(asset_server: &Res<AssetServer>, config: &Res<ConfigData>, player: usize, fst: bool) -> impl Bundle {
     let texture_handle: Handle<Image> = asset_server.load(config.game_config.preview_img.clone());
-    (Sprite::from_image(texture_handle), calculate_preview_transform(config, fst))
+    (Sprite::from_image(texture_handle), calculate_preview_transform(config, player, fst))
     // SpriteBundle {
     //     sprite: Sprite {
     //         ..default()
@@ -114,12 +198,13 @@ pub fn preview_board(asset_server: &Res<AssetServer>, config: &Res<ConfigData>,
 
 
 
-pub fn main_board(asset_server: &Res<AssetServer>, config: &Res<ConfigData>) -> impl Bundle {
+pub fn main_board(asset_server: &Res<AssetServer>, config: &Res<ConfigData>, player: usize) -> impl Bundle {
 
     let texture_handle: Handle<Image> = asset_server.load(config.game_config.border_img.clone());
+    let x = board_offset(player, config.game_config.tile_size * config.game_config.scale_factor);
     (Sprite::from_image(texture_handle),
         Transform::from_scale(Vec3::new(config.game_config.scale_factor, config.game_config.scale_factor, 1.0))
-            .with_translation(Vec3::new(0.0, 0.0, 0.0)))
+            .with_translation(Vec3::new(x, 0.0, 0.0)))
 
     // SpriteBundle {
     //     sprite: Sprite {
@@ -130,3 +215,31 @@ pub fn main_board(asset_server: &Res<AssetServer>, config: &Res<ConfigData>) ->
     //     ..default()
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_stop_at_the_end_of_a_fixed_queue() {
+        let mut rng = GameRng::new(1);
+        rng.queue = vec![5, 0, 3].into();
+        rng.queue_only = true;
+        let mut shown = vec![];
+        for _ in 0..5 {
+            rng.next_piece(Randomizer::Uniform, 0);
+            shown.push(rng.shown_previews(2));
+        }
+        assert_eq!(shown, [2, 2, 2, 1, 0]);
+        assert_eq!(rng.shown_previews(1), 0);
+    }
+
+    #[test]
+    fn random_pieces_always_fill_the_previews() {
+        let mut rng = GameRng::new(1);
+        for _ in 0..5 {
+            rng.next_piece(Randomizer::Uniform, 0);
+        }
+        assert_eq!(rng.shown_previews(2), 2);
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::fs;
+
+use bevy::prelude::*;
+use ini::ini;
+
+use crate::game_logic::AppState;
+use crate::modes::{GameMode, GM_CHECKPOINTS};
+use crate::rules::Scoring;
+use crate::versus::Versus;
+
+//个人最佳记录文件路径
+pub const RECORDS_PATH: &str = "records.ini";
+
+//禅模式跨局累计的统计数据文件路径
+pub const ZEN_PATH: &str = "zen.ini";
+
+//连锁模式中每一级连锁的奖励分，再乘以连锁数和等级
+const CHAIN_BONUS: u32 = 50;
+
+//每消除多少行记录一次分段用时
+pub const SPLIT_LINES: u32 = 10;
+
+//本局的统计数据，每局开始时重置
+#[derive(Resource, Debug, Clone)]
+pub struct GameStats {
+    pub score: u32,
+    pub level: u32,
+    pub lines: u32,
+    pub pieces: u32,
+    //游戏进行的秒数，不包括暂停
+    pub elapsed: f64,
+    //暂停次数
+    pub pauses: u32,
+    //计时是否已经开始
+    pub timer_started: bool,
+    //每消除10行时的用时
+    pub splits: Vec<f64>,
+    //一次消除1到4行的次数
+    pub clears: [u32; 4],
+    //T旋的次数，按同时消除的行数(0到3)分开统计
+    pub tspins: [u32; 4],
+    //全部消除的次数
+    pub perfect_clears: u32,
+    //连锁模式中最长的连锁数
+    pub max_chain: u32,
+    //消除的垃圾行数
+    pub garbage_lines: u32,
+    //等级上限，由模式决定
+    pub max_level: u32,
+    pub scoring: Scoring,
+    //大师模式中每段(100级)完成时的用时
+    pub sections: Vec<f64>,
+    //大师模式中是否通过了目前所有的GM检查点
+    pub gm_eligible: bool,
+}
+
+impl Default for GameStats {
+    fn default() -> Self {
+        Self {
+            score: 0,
+            level: 1,
+            lines: 0,
+            pieces: 0,
+            elapsed: 0.0,
+            pauses: 0,
+            timer_started: false,
+            splits: vec![],
+            clears: [0; 4],
+            tspins: [0; 4],
+            perfect_clears: 0,
+            max_chain: 0,
+            garbage_lines: 0,
+            max_level: u32::MAX,
+            scoring: Scoring::Guideline,
+            sections: vec![],
+            gm_eligible: true,
+        }
+    }
+}
+
+impl GameStats {
+    //方块落定，大师模式中每个方块升一级，但每段的最后一级和最高等级前的一级需要消行
+    pub fn add_piece(&mut self) {
+        self.pieces += 1;
+        if self.scoring == Scoring::Master && self.level % 100 != 99 && self.level + 1 != self.max_level {
+            self.advance_master_level(1);
+        }
+    }
+
+    //连锁消除，按普通消除计分后再加上连锁奖励
+    //方块落定时的消除由clear_lines按普通消除计分，不算连锁，之后方块下落引起的第一次消除chain为1
+    pub fn add_chain(&mut self, chain: u32, count: u32) {
+        self.add_lines(count);
+        self.score += CHAIN_BONUS * chain * self.level.max(1);
+        self.max_chain = self.max_chain.max(chain);
+    }
+
+    //消除行后更新行数、分数和等级，标准规则每10行升一级，并记录分段用时
+    pub fn add_lines(&mut self, count: u32) {
+        if count == 0 {
+            return;
+        }
+        match self.scoring {
+            Scoring::Guideline => self.score += line_clear_score(count) * self.level,
+            Scoring::Nes => self.score += nes_line_clear_score(count) * (self.level + 1),
+            //(消行前等级 + 行数) / 4 向上取整，再乘以行数
+            Scoring::Master => self.score += (self.level + count).div_ceil(4) * count,
+        }
+        if (1..=4).contains(&count) {
+            self.clears[count as usize - 1] += 1;
+        }
+        for _ in (self.lines / SPLIT_LINES)..((self.lines + count) / SPLIT_LINES) {
+            self.splits.push(self.elapsed);
+        }
+        self.lines += count;
+        match self.scoring {
+            Scoring::Guideline => self.level = (self.lines / 10 + 1).min(self.max_level),
+            Scoring::Nes => self.level = (self.lines / 10).min(self.max_level),
+            Scoring::Master => self.advance_master_level(count),
+        }
+    }
+
+    //达成目标的操作发生在本步之前lag秒，把按步长累计的用时和本步记录的分段用时退回到操作的时间
+    pub fn finish_early(&mut self, lag: f64) {
+        let finished = (self.elapsed - lag).max(0.0);
+        for split in self.splits.iter_mut().filter(|split| **split == self.elapsed) {
+            *split = finished;
+        }
+        self.elapsed = finished;
+    }
+
+    //大师模式升级，经过每段时记录用时并检查GM条件
+    fn advance_master_level(&mut self, amount: u32) {
+        let before = self.level;
+        self.level = (self.level + amount).min(self.max_level);
+        for section in (before / 100 + 1)..=(self.level / 100) {
+            self.sections.push(self.elapsed);
+            self.check_gm(section * 100);
+        }
+        if self.level >= self.max_level && before < self.max_level {
+            self.check_gm(self.max_level);
+        }
+    }
+
+    fn check_gm(&mut self, level: u32) {
+        for (checkpoint, time, score) in GM_CHECKPOINTS {
+            if checkpoint == level && (self.elapsed > time || self.score < score) {
+                self.gm_eligible = false;
+            }
+        }
+    }
+
+    //每秒放置的方块数
+    pub fn pieces_per_second(&self) -> f64 {
+        if self.elapsed > 0.0 {
+            self.pieces as f64 / self.elapsed
+        } else {
+            0.0
+        }
+    }
+
+    pub fn to_record(&self) -> Record {
+        Record {
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+            time: self.elapsed,
+        }
+    }
+}
+
+//从游戏中暂停时计数，从设置界面返回暂停不计，对战中两个玩家一起暂停
+pub fn count_pause(
+    mut game_stats: ResMut<GameStats>,
+    mut versus: ResMut<Versus>,
+) {
+    game_stats.pauses += 1;
+    if let Some(second) = versus.second_stats_mut() {
+        second.pauses += 1;
+    }
+}
+
+//一次消除的基础分
+pub fn line_clear_score(count: u32) -> u32 {
+    match count {
+        1 => 100,
+        2 => 300,
+        3 => 500,
+        4 => 800,
+        _ => 0
+    }
+}
+
+//NES规则一次消除的基础分
+pub fn nes_line_clear_score(count: u32) -> u32 {
+    match count {
+        1 => 40,
+        2 => 100,
+        3 => 300,
+        4 => 1200,
+        _ => 0
+    }
+}
+
+//格式化为 分:秒.毫秒
+pub fn format_time(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!("{}:{:02}.{:03}", millis / 60000, millis / 1000 % 60, millis % 1000)
+}
+
+//一局的最终成绩
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub score: u32,
+    pub lines: u32,
+    pub level: u32,
+    pub time: f64,
+}
+
+//每个模式的个人最佳记录
+#[derive(Resource, Debug, Default)]
+pub struct PersonalBests {
+    pub records: HashMap<String, Record>,
+}
+
+impl PersonalBests {
+    pub fn get(&self, mode: GameMode) -> Option<&Record> {
+        self.records.get(&mode.record_key())
+    }
+
+    //成绩比之前更好时更新记录，返回是否为新纪录
+    pub fn submit(&mut self, mode: GameMode, record: Record) -> bool {
+        let better = match self.get(mode) {
+            Some(best) => mode.is_better(&record, best),
+            None => true,
+        };
+        if better {
+            self.records.insert(mode.record_key(), record);
+        }
+        better
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let mut keys: Vec<&String> = self.records.keys().collect();
+        keys.sort();
+        let mut content = String::new();
+        for key in keys {
+            let record = &self.records[key];
+            content.push_str(&format!("[{}]\n", key));
+            content.push_str(&format!("score = {}\n", record.score));
+            content.push_str(&format!("lines = {}\n", record.lines));
+            content.push_str(&format!("level = {}\n", record.level));
+            content.push_str(&format!("time = {}\n\n", record.time));
+        }
+        fs::write(path, content)
+    }
+}
+
+//读取个人最佳记录，文件不存在时为空
+pub fn load_records(path: &str) -> PersonalBests {
+    let mut bests = PersonalBests::default();
+    let Ok(map) = ini!(safe path) else {
+        return bests;
+    };
+    for (key, values) in map.iter() {
+        let read = |name: &str| values.get(name).cloned().flatten().and_then(|v| v.parse::<f64>().ok());
+        let (Some(score), Some(lines), Some(level), Some(time)) = (read("score"), read("lines"), read("level"), read("time")) else {
+            warn!("invalid record for {} in {}", key, path);
+            continue;
+        };
+        bests.records.insert(key.clone(), Record {
+            score: score as u32,
+            lines: lines as u32,
+            level: level as u32,
+            time,
+        });
+    }
+    bests
+}
+
+//读取禅模式累计的统计数据，文件不存在时从零开始
+pub fn load_zen_stats(path: &str, stats: &mut GameStats) {
+    let Ok(map) = ini!(safe path) else {
+        return;
+    };
+    let Some(values) = map.get("zen") else {
+        return;
+    };
+    let read = |name: &str| values.get(name).cloned().flatten().and_then(|v| v.parse::<f64>().ok()).unwrap_or(0.0);
+    stats.score = read("score") as u32;
+    stats.lines = read("lines") as u32;
+    stats.pieces = read("pieces") as u32;
+    stats.elapsed = read("elapsed");
+    for (i, clears) in stats.clears.iter_mut().enumerate() {
+        *clears = read(&format!("clears{}", i + 1)) as u32;
+    }
+    stats.level = stats.scoring.start_level() + stats.lines / 10;
+}
+
+pub fn save_zen_stats(path: &str, stats: &GameStats) -> std::io::Result<()> {
+    let mut content = String::from("[zen]\n");
+    content.push_str(&format!("score = {}\n", stats.score));
+    content.push_str(&format!("lines = {}\n", stats.lines));
+    content.push_str(&format!("pieces = {}\n", stats.pieces));
+    content.push_str(&format!("elapsed = {}\n", stats.elapsed));
+    for (i, clears) in stats.clears.iter().enumerate() {
+        content.push_str(&format!("clears{} = {}\n", i + 1, clears));
+    }
+    fs::write(path, content)
+}
+
+//禅模式开始时接着上次的统计数据
+pub fn resume_zen(
+    mode: Res<GameMode>,
+    mut game_stats: ResMut<GameStats>,
+) {
+    if *mode == GameMode::Zen {
+        load_zen_stats(ZEN_PATH, &mut game_stats);
+    }
+}
+
+//离开游戏(暂停、重新开始或退出)和关闭窗口时保存禅模式的统计数据
+pub fn save_zen(
+    mode: Res<GameMode>,
+    game_stats: Res<GameStats>,
+) {
+    if *mode != GameMode::Zen {
+        return;
+    }
+    if let Err(err) = save_zen_stats(ZEN_PATH, &game_stats) {
+        error!("failed to save {}: {}", ZEN_PATH, err);
+    }
+}
+
+//游戏结束时的成绩汇总，供结束界面显示
+#[derive(Resource, Debug, Clone)]
+pub struct GameSummary {
+    pub mode: GameMode,
+    pub stats: GameStats,
+    //本局之前的个人最佳
+    pub previous_best: Option<Record>,
+    pub new_best: bool,
+    //是否达成了模式目标
+    pub completed: bool,
+    //已保存的回放文件路径
+    pub replay_path: Option<String>,
+    //对战模式中获胜的玩家
+    pub winner: Option<usize>,
+    //对战模式中每个玩家发送的垃圾行数
+    pub garbage_sent: [u32; 2],
+    //对战模式中第二个玩家的统计数据
+    pub second_stats: Option<GameStats>,
+}
+
+//游戏结束时汇总成绩并更新个人最佳，需要完成目标的模式未完成时不计入
+pub fn finish_game(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    state: Res<State<AppState>>,
+    game_stats: Res<GameStats>,
+    mut bests: ResMut<PersonalBests>,
+    versus: Res<Versus>,
+) {
+    let completed = *state.get() == AppState::FINISHED;
+    let previous_best = bests.get(*mode).copied();
+    let new_best = (completed || !mode.requires_completion()) && bests.submit(*mode, game_stats.to_record());
+    if new_best {
+        if let Err(err) = bests.save(RECORDS_PATH) {
+            error!("failed to save {}: {}", RECORDS_PATH, err);
+        }
+    }
+    commands.insert_resource(GameSummary {
+        mode: *mode,
+        stats: game_stats.clone(),
+        previous_best,
+        new_best,
+        completed,
+        replay_path: None,
+        winner: versus.loser.filter(|_| *mode == GameMode::Versus).map(|loser| 1 - loser),
+        garbage_sent: versus.sent,
+        second_stats: versus.second_stats().filter(|_| *mode == GameMode::Versus).cloned(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finish_early_rewinds_time_and_last_split() {
+        let mut stats = GameStats { elapsed: 10.0, splits: vec![4.0, 10.0], ..default() };
+        stats.finish_early(0.012);
+        assert!((stats.elapsed - 9.988).abs() < 1e-9);
+        assert_eq!(stats.splits[0], 4.0);
+        assert_eq!(stats.splits[1], stats.elapsed);
+    }
+
+    //大师模式中从level消除一行，消除后分数正好为score
+    fn clear_into(level: u32, score: u32, elapsed: f64) -> GameStats {
+        let mut stats = GameStats {
+            scoring: Scoring::Master,
+            level,
+            max_level: crate::modes::MASTER_MAX_LEVEL,
+            elapsed,
+            ..default()
+        };
+        stats.score = score - (level + 1).div_ceil(4);
+        stats.add_lines(1);
+        assert_eq!((stats.level, stats.score), (level + 1, score));
+        stats
+    }
+
+    #[test]
+    fn gm_checkpoints_pass_at_their_limits() {
+        for (checkpoint, time, score) in GM_CHECKPOINTS {
+            assert!(clear_into(checkpoint - 1, score, time).gm_eligible, "level {}", checkpoint);
+        }
+    }
+
+    #[test]
+    fn missing_a_gm_checkpoint_loses_eligibility() {
+        for (checkpoint, time, score) in GM_CHECKPOINTS {
+            assert!(!clear_into(checkpoint - 1, score - 1, time).gm_eligible, "level {}", checkpoint);
+            assert!(!clear_into(checkpoint - 1, score, time + 0.5).gm_eligible, "level {}", checkpoint);
+        }
+    }
+
+    #[test]
+    fn only_a_line_clear_reaches_the_final_level() {
+        let mut stats = GameStats {
+            scoring: Scoring::Master,
+            level: crate::modes::MASTER_MAX_LEVEL - 1,
+            max_level: crate::modes::MASTER_MAX_LEVEL,
+            ..default()
+        };
+        stats.add_piece();
+        assert_eq!(stats.level, crate::modes::MASTER_MAX_LEVEL - 1);
+        stats.add_lines(1);
+        assert_eq!(stats.level, crate::modes::MASTER_MAX_LEVEL);
+    }
+
+    #[test]
+    fn pieces_stop_at_the_end_of_each_section() {
+        let mut stats = GameStats { scoring: Scoring::Master, level: 98, max_level: 999, ..default() };
+        stats.add_piece();
+        stats.add_piece();
+        assert_eq!(stats.level, 99);
+    }
+
+    #[test]
+    fn zen_stats_round_trip() {
+        let path = std::env::temp_dir().join(format!("tetris-zen-{}.ini", std::process::id()));
+        let path = path.to_str().unwrap();
+        let saved = GameStats {
+            score: 12345,
+            lines: 37,
+            pieces: 100,
+            elapsed: 123.5,
+            clears: [10, 5, 2, 1],
+            ..default()
+        };
+        save_zen_stats(path, &saved).unwrap();
+        let mut loaded = GameStats::default();
+        load_zen_stats(path, &mut loaded);
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.score, saved.score);
+        assert_eq!(loaded.lines, saved.lines);
+        assert_eq!(loaded.pieces, saved.pieces);
+        assert_eq!(loaded.elapsed, saved.elapsed);
+        assert_eq!(loaded.clears, saved.clears);
+        //等级由消除的行数重新计算
+        assert_eq!(loaded.level, 4);
+    }
+
+    #[test]
+    fn missing_zen_stats_start_from_zero() {
+        let path = std::env::temp_dir().join("tetris-zen-missing.ini");
+        let mut stats = GameStats::default();
+        load_zen_stats(path.to_str().unwrap(), &mut stats);
+        assert_eq!(stats.score, 0);
+        assert_eq!(stats.lines, 0);
+        assert_eq!(stats.level, 1);
+    }
+}
@@ -0,0 +1,483 @@
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+
+use crate::config::{ConfigData, KeyCapture};
+use crate::game_logic::{AppState, PlayerPreviewQuery, TileBoard};
+use crate::garbage::GarbageState;
+use crate::hud;
+use crate::input::{Action, Actions, InputBuffer, RepeatState};
+use crate::keys;
+use crate::modes::GameMode;
+use crate::replay::Replay;
+use crate::scene::{self, GameRng, GameState, Player};
+use crate::stats::GameStats;
+
+//对战时相机缩小的倍数，让两个棋盘都能放进窗口
+const VERSUS_ZOOM: f32 = 1.3;
+//消除0到4行的攻击行数
+const LINE_ATTACK: [u32; 5] = [0, 0, 1, 2, 4];
+//T旋消除0到3行的攻击行数
+const TSPIN_ATTACK: [u32; 4] = [0, 2, 4, 6];
+//完美消除额外的攻击行数
+const PERFECT_CLEAR_ATTACK: u32 = 10;
+//垃圾行计量条的宽度(格)
+const METER_WIDTH: f32 = 0.35;
+
+//每局开始时为一个棋盘重置状态并生成第一个方块
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BoardStart;
+
+//把一个棋盘记录的输入转换为本步的操作
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BoardInput;
+
+//一个棋盘在一个固定步长中的游戏逻辑
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BoardTick;
+
+//一次落定发送的垃圾行数
+pub fn attack(lines: u32, tspin: bool, perfect_clear: bool) -> u32 {
+    let base = if tspin {
+        TSPIN_ATTACK[lines.min(3) as usize]
+    } else {
+        LINE_ATTACK[lines.min(4) as usize]
+    };
+    if perfect_clear { base + PERFECT_CLEAR_ATTACK } else { base }
+}
+
+//第二个棋盘的全部状态，轮到它时与单例资源交换
+pub struct StoredBoard {
+    state: GameState,
+    tile_board: TileBoard,
+    stats: GameStats,
+    rng: GameRng,
+    garbage: GarbageState,
+    input: InputBuffer,
+    repeat: RepeatState,
+    actions: Actions,
+    replay: Replay,
+}
+
+impl StoredBoard {
+    fn new(tile_board: TileBoard) -> Self {
+        let mut rng = GameRng::default();
+        Self {
+            state: scene::init_game_state(&mut rng.rng),
+            tile_board,
+            stats: GameStats::default(),
+            rng,
+            garbage: GarbageState::default(),
+            input: InputBuffer::default(),
+            repeat: RepeatState::default(),
+            actions: Actions::default(),
+            replay: Replay::default(),
+        }
+    }
+
+    fn swap(&mut self, world: &mut World) {
+        std::mem::swap(&mut *world.resource_mut::<GameState>(), &mut self.state);
+        std::mem::swap(&mut *world.resource_mut::<TileBoard>(), &mut self.tile_board);
+        std::mem::swap(&mut *world.resource_mut::<GameStats>(), &mut self.stats);
+        std::mem::swap(&mut *world.resource_mut::<GameRng>(), &mut self.rng);
+        std::mem::swap(&mut *world.resource_mut::<GarbageState>(), &mut self.garbage);
+        std::mem::swap(&mut *world.resource_mut::<InputBuffer>(), &mut self.input);
+        std::mem::swap(&mut *world.resource_mut::<RepeatState>(), &mut self.repeat);
+        std::mem::swap(&mut *world.resource_mut::<Actions>(), &mut self.actions);
+        std::mem::swap(&mut *world.resource_mut::<Replay>(), &mut self.replay);
+    }
+}
+
+//对战状态，单人游戏中没有第二个棋盘
+#[derive(Resource, Default)]
+pub struct Versus {
+    //第二个玩家的棋盘，平时存放在这里，0号棋盘的状态一直在单例资源中
+    pub second: Option<StoredBoard>,
+    //当前在单例资源中运行的棋盘
+    pub active: usize,
+    //每个棋盘等待升起的垃圾行数
+    pub incoming: [u32; 2],
+    //每个玩家发送的垃圾行数，不含被抵消的部分
+    pub sent: [u32; 2],
+    //先堆到顶的玩家
+    pub loser: Option<usize>,
+    //上一次落定时的消行数、T旋数和完美消除数，用来计算本次落定的攻击
+    seen: [(u32, u32, u32); 2],
+}
+
+impl Versus {
+    //第二个玩家本局的统计数据
+    pub fn second_stats(&self) -> Option<&GameStats> {
+        self.second.as_ref().map(|second| &second.stats)
+    }
+
+    pub fn second_stats_mut(&mut self) -> Option<&mut GameStats> {
+        self.second.as_mut().map(|second| &mut second.stats)
+    }
+}
+
+//把第二个棋盘换入单例资源执行run，结束后换回
+fn with_second_board(world: &mut World, run: impl FnOnce(&mut World)) {
+    let Some(mut second) = world.resource_mut::<Versus>().second.take() else {
+        return;
+    };
+    second.swap(world);
+    world.resource_mut::<Versus>().active = 1;
+    run(world);
+    second.swap(world);
+    let mut versus = world.resource_mut::<Versus>();
+    versus.active = 0;
+    versus.second = Some(second);
+}
+
+//本步是否已经要离开游戏(暂停或结束)，此时另一个棋盘不再运行
+fn leaving(world: &World) -> bool {
+    matches!(world.resource::<NextState<AppState>>(), NextState::Pending(_))
+}
+
+//一个棋盘运行后记录是否堆到顶了
+fn check_loser(world: &mut World, player: usize) {
+    if matches!(world.resource::<NextState<AppState>>(), NextState::Pending(AppState::DEAD)) {
+        world.resource_mut::<Versus>().loser = Some(player);
+    }
+}
+
+//每局开始时清除上一局对战的棋盘，不是对战模式时丢弃第二个棋盘
+pub fn reset_versus(
+    mut commands: Commands,
+    mode: Res<GameMode>,
+    mut versus: ResMut<Versus>,
+    preview_query: PlayerPreviewQuery,
+) {
+    let second = versus.second.take();
+    *versus = Versus::default();
+    if *mode == GameMode::Versus {
+        versus.second = second;
+        return;
+    }
+    if let Some(second) = second {
+        for entity in second.tile_board.get_all_entitys() {
+            commands.entity(entity).despawn();
+        }
+    }
+    for (entity, player) in preview_query.iter() {
+        if player.0 != 0 {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+//对战模式中的边框、标签和垃圾行计量条
+#[derive(Component, Debug)]
+pub struct VersusDecor;
+
+//显示等待升起的垃圾行数的计量条
+#[derive(Component, Debug)]
+pub struct IncomingMeter;
+
+//对战模式中生成第二个棋盘的边框、玩家标签和计量条，并缩小相机让两个棋盘都能看到
+pub fn spawn_versus_scene(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<ConfigData>,
+    mode: Res<GameMode>,
+    decor_query: Query<Entity, With<VersusDecor>>,
+    mut camera_query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    for entity in decor_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    let versus = *mode == GameMode::Versus;
+    let tile = config.game_config.tile_size * config.game_config.scale_factor;
+    for (mut transform, mut projection) in camera_query.iter_mut() {
+        transform.translation.x = if versus { scene::board_offset(1, tile) / 2.0 } else { 0.0 };
+        projection.scale = if versus { VERSUS_ZOOM } else { 1.0 };
+    }
+    if !versus {
+        return;
+    }
+    commands.spawn((scene::main_board(&asset_server, &config, 1), VersusDecor));
+    commands.spawn((scene::preview_board(&asset_server, &config, 1, true), VersusDecor));
+    commands.spawn((scene::preview_board(&asset_server, &config, 1, false), scene::SndPreviewFrame, VersusDecor));
+    for entity in hud::spawn_fields(&mut commands, &asset_server, &config, 1) {
+        commands.entity(entity).insert(VersusDecor);
+    }
+    for player in 0..2 {
+        let x = scene::board_offset(player, tile);
+        commands.spawn((
+            Text2d::new(format!("P{}", player + 1)),
+            TextFont {
+                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                font_size: tile * 0.8,
+                ..default()
+            },
+            TextColor(Color::WHITE),
+            Anchor::BottomCenter,
+            Transform::from_xyz(x, tile * 10.5, 1.0),
+            VersusDecor,
+        ));
+        //计量条从棋盘左侧的底部向上增长
+        commands.spawn((
+            Sprite {
+                anchor: Anchor::BottomCenter,
+                ..Sprite::from_color(Color::srgb(0.9, 0.2, 0.2), Vec2::new(tile * METER_WIDTH, 0.0))
+            },
+            Transform::from_xyz(x - tile * 5.5, -tile * 10.0, 2.0),
+            IncomingMeter,
+            Player(player),
+            VersusDecor,
+        ));
+    }
+}
+
+//为每个棋盘重置状态并生成第一个方块，对战模式中第一次开始时创建第二个棋盘
+pub fn start_boards(world: &mut World) {
+    if *world.resource::<GameMode>() == GameMode::Versus && world.resource::<Versus>().second.is_none() {
+        let board = world.resource::<TileBoard>();
+        let tile_board = TileBoard {
+            player: 1,
+            ..TileBoard::new(board.width, board.height, board.scale_factor, board.tile_size)
+        };
+        world.resource_mut::<Versus>().second = Some(StoredBoard::new(tile_board));
+    }
+    world.run_schedule(BoardStart);
+    with_second_board(world, |world| world.run_schedule(BoardStart));
+}
+
+//依次运行每个棋盘的游戏逻辑，一方堆到顶或暂停后另一方本步不再运行
+pub fn tick_boards(world: &mut World) {
+    world.run_schedule(BoardTick);
+    check_loser(world, 0);
+    if leaving(world) {
+        return;
+    }
+    with_second_board(world, |world| {
+        world.run_schedule(BoardInput);
+        world.run_schedule(BoardTick);
+        check_loser(world, 1);
+    });
+}
+
+//方块落定后计算攻击：先抵消自己等待中的垃圾行，剩下的发给对手，没有消行时升起等待中的垃圾行
+pub fn exchange_garbage(
+    mode: Res<GameMode>,
+    game_stats: Res<GameStats>,
+    mut versus: ResMut<Versus>,
+    mut garbage: ResMut<GarbageState>,
+) {
+    if *mode != GameMode::Versus {
+        return;
+    }
+    let player = versus.active;
+    let opponent = 1 - player;
+    let tspins = game_stats.tspins.iter().sum::<u32>();
+    let (lines, seen_tspins, perfect_clears) = versus.seen[player];
+    let cleared = game_stats.lines - lines;
+    let sent = attack(cleared, tspins > seen_tspins, game_stats.perfect_clears > perfect_clears);
+    versus.seen[player] = (game_stats.lines, tspins, game_stats.perfect_clears);
+
+    let cancelled = sent.min(versus.incoming[player]);
+    versus.incoming[player] -= cancelled;
+    versus.incoming[opponent] += sent - cancelled;
+    versus.sent[player] += sent - cancelled;
+    if cleared == 0 {
+        garbage.pending += std::mem::take(&mut versus.incoming[player]);
+    }
+}
+
+//记录第二个玩家的键盘输入，第一个玩家的输入由input::record_input记录
+//第二个棋盘的缓冲只在RUNNING中消费，其他状态下不记录，避免菜单中的方向键在恢复后变成操作
+pub fn record_second_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    config: Res<ConfigData>,
+    capture: Res<KeyCapture>,
+    time: Res<Time<Virtual>>,
+    state: Res<State<AppState>>,
+    mut versus: ResMut<Versus>,
+) {
+    if *state.get() != AppState::RUNNING {
+        return;
+    }
+    let Some(second) = versus.second.as_mut() else {
+        return;
+    };
+    //正在设置按键时视为全部松开，不产生任何操作
+    let capturing = capture.action.is_some();
+    for action in Action::ALL {
+        let key = keys::from_str(config.versus_keys_config.get(action));
+        let down = !capturing && keyboard_input.pressed(key);
+        let tapped = !capturing && keyboard_input.just_pressed(key);
+        second.input.record(action, down, tapped, time.elapsed());
+    }
+}
+
+//离开游戏状态时停止第二个棋盘的长按重复
+pub fn stop_second_repeat(
+    mut versus: ResMut<Versus>,
+) {
+    if let Some(second) = versus.second.as_mut() {
+        second.repeat.key_press_start_frame = None;
+    }
+}
+
+//暂停时隐藏第二个棋盘，预览由game_logic::hide_board隐藏
+pub fn hide_second_board(
+    commands: Commands,
+    versus: Res<Versus>,
+) {
+    set_second_visibility(commands, &versus, Visibility::Hidden);
+}
+
+pub fn show_second_board(
+    commands: Commands,
+    versus: Res<Versus>,
+) {
+    set_second_visibility(commands, &versus, Visibility::Inherited);
+}
+
+fn set_second_visibility(
+    mut commands: Commands,
+    versus: &Versus,
+    visibility: Visibility,
+) {
+    let Some(second) = versus.second.as_ref() else {
+        return;
+    };
+    for entity in second.tile_board.get_all_entitys() {
+        commands.entity(entity).insert(visibility);
+    }
+}
+
+//按等待升起的垃圾行数设置计量条的高度
+pub fn update_meters(
+    versus: Res<Versus>,
+    config: Res<ConfigData>,
+    mut query: Query<(&mut Sprite, &Player), With<IncomingMeter>>,
+) {
+    let tile = config.game_config.tile_size * config.game_config.scale_factor;
+    for (mut sprite, player) in query.iter_mut() {
+        let rows = versus.incoming[player.0].min(20);
+        let size = Some(Vec2::new(tile * METER_WIDTH, rows as f32 * tile));
+        if sprite.custom_size != size {
+            sprite.custom_size = size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    //0号玩家落定后交换垃圾行，返回对战状态和0号玩家等待升起的行数
+    fn exchange(mode: GameMode, versus: Versus, game_stats: GameStats) -> (Versus, u32) {
+        let mut world = World::new();
+        world.insert_resource(mode);
+        world.insert_resource(versus);
+        world.insert_resource(game_stats);
+        world.insert_resource(GarbageState::default());
+        world.run_system_once(exchange_garbage).unwrap();
+        let pending = world.resource::<GarbageState>().pending;
+        (world.remove_resource::<Versus>().unwrap(), pending)
+    }
+
+    fn incoming(rows: u32) -> Versus {
+        Versus { incoming: [rows, 0], ..default() }
+    }
+
+    fn cleared(lines: u32) -> GameStats {
+        GameStats { lines, ..default() }
+    }
+
+    #[test]
+    fn attack_follows_the_tables() {
+        let lines: Vec<u32> = (0..=4).map(|lines| attack(lines, false, false)).collect();
+        assert_eq!(lines, [0, 0, 1, 2, 4]);
+        let tspins: Vec<u32> = (0..=3).map(|lines| attack(lines, true, false)).collect();
+        assert_eq!(tspins, [0, 2, 4, 6]);
+        assert_eq!(attack(2, false, true), 11);
+        assert_eq!(attack(4, false, true), 14);
+    }
+
+    #[test]
+    fn attack_cancels_incoming_garbage_first() {
+        //消除2行攻击1行，全部用来抵消
+        let (versus, pending) = exchange(GameMode::Versus, incoming(3), cleared(2));
+        assert_eq!(versus.incoming, [2, 0]);
+        assert_eq!(versus.sent, [0, 0]);
+        assert_eq!(pending, 0);
+    }
+
+    #[test]
+    fn attack_left_after_cancelling_goes_to_the_opponent() {
+        //消除4行攻击4行，抵消1行后发送3行
+        let (versus, pending) = exchange(GameMode::Versus, incoming(1), cleared(4));
+        assert_eq!(versus.incoming, [0, 3]);
+        assert_eq!(versus.sent, [3, 0]);
+        assert_eq!(pending, 0);
+    }
+
+    #[test]
+    fn incoming_garbage_rises_only_without_a_clear() {
+        let (versus, pending) = exchange(GameMode::Versus, incoming(3), cleared(0));
+        assert_eq!(versus.incoming, [0, 0]);
+        assert_eq!(pending, 3);
+        //只有之前消除的行数，本次落定没有消行
+        let mut versus = incoming(2);
+        versus.seen[0] = (4, 0, 0);
+        let (versus, pending) = exchange(GameMode::Versus, versus, cleared(4));
+        assert_eq!(versus.incoming, [0, 0]);
+        assert_eq!(pending, 2);
+    }
+
+    //按state记录一帧第二个玩家的键盘输入
+    fn record_frame(world: &mut World, state: AppState, key: KeyCode, down: bool) {
+        world.insert_resource(State::new(state));
+        let mut keyboard = world.resource_mut::<ButtonInput<KeyCode>>();
+        keyboard.clear();
+        if down {
+            keyboard.press(key);
+        } else {
+            keyboard.release(key);
+        }
+        world.run_system_once(record_second_input).unwrap();
+    }
+
+    #[test]
+    fn second_player_presses_while_paused_are_dropped() {
+        let mut world = World::new();
+        let config = ConfigData::new();
+        let drop_key = keys::from_str(config.versus_keys_config.get(Action::Drop));
+        world.insert_resource(config);
+        world.insert_resource(ButtonInput::<KeyCode>::default());
+        world.insert_resource(KeyCapture::default());
+        world.insert_resource(Time::<Virtual>::default());
+        world.insert_resource(Versus {
+            second: Some(StoredBoard::new(TileBoard::new(10, 20, 1.0, 1.0))),
+            ..default()
+        });
+        //暂停菜单中用方向键选择，松开后恢复游戏
+        record_frame(&mut world, AppState::PAUSED, drop_key, true);
+        record_frame(&mut world, AppState::PAUSED, drop_key, false);
+        record_frame(&mut world, AppState::RUNNING, drop_key, false);
+
+        //恢复后的第一步不应该产生任何操作
+        let mut second = world.resource_mut::<Versus>().second.take().unwrap();
+        let mut board = World::new();
+        board.insert_resource(ConfigData::new());
+        board.insert_resource(crate::rules::Ruleset::default());
+        board.insert_resource(Time::<Fixed>::default());
+        board.insert_resource(std::mem::take(&mut second.input));
+        board.insert_resource(RepeatState::default());
+        board.insert_resource(Actions::default());
+        board.run_system_once(crate::input::process_input).unwrap();
+        assert!(board.resource::<Actions>().0.is_empty());
+    }
+
+    #[test]
+    fn exchange_only_runs_in_versus() {
+        let (versus, pending) = exchange(GameMode::Endless, incoming(3), cleared(0));
+        assert_eq!(versus.incoming, [3, 0]);
+        assert_eq!(pending, 0);
+    }
+}